@@ -0,0 +1,372 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::catalog::Catalog;
+use crate::classify::{self, Classification};
+use crate::corrections::Corrections;
+use crate::documents::{self, DocumentDb, SourceText};
+use crate::extraction::{AnalysisResult, Extraction};
+use crate::factur_x;
+use crate::fattura_pa;
+use crate::filename::{self, FileMetadata};
+use crate::filename_schema::FilenameSchema;
+use crate::llm::{self, LlmProvider};
+use crate::ocr::{self, OcrBackend};
+use crate::pdf_text::{self, PdfTextExtractor, TextMode};
+use crate::retry::{self, Http, RetryPolicy};
+use crate::settings::Settings;
+use crate::usage::UsageStep;
+
+const PROMPT_AUFTRAG: &str = include_str!("../../src/prompts/PromptAuftrag.txt");
+const PROMPT_RECHNUNG: &str = include_str!("../../src/prompts/PromptRechnung.txt");
+const PROMPT_LIEFERSCHEIN: &str = include_str!("../../src/prompts/PromptLieferschein.txt");
+const PROMPT_GUTSCHRIFT: &str = include_str!("../../src/prompts/PromptGutschrift.txt");
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalysisStage {
+    Extracting,
+    Ocr,
+    Llm,
+}
+
+pub struct Analyzer {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    provider: Box<dyn LlmProvider>,
+    ocr: Box<dyn OcrBackend>,
+    extractors: Vec<Box<dyn PdfTextExtractor>>,
+    corrections: Corrections,
+    catalog: Catalog,
+    document_db: Option<PathBuf>,
+    batch_id: Option<String>,
+    filenames: FilenameSchema,
+}
+
+impl Analyzer {
+    pub fn new(
+        settings: &Settings,
+        corrections: &Settings,
+        api_key: &dyn Fn(&str) -> String,
+        pdftotext: Box<dyn PdfTextExtractor>,
+    ) -> Analyzer {
+        // Keys are checked by the provider and OCR backend when they are first used,
+        // so documents that never reach them (FatturaPA, Factur-X, cache) still work.
+        let profile = llm::load_active_profile(settings);
+        let llm_key = profile.key_entry().map(|entry| api_key(&entry));
+        let ocr_settings = ocr::load_settings(settings);
+        let mistral_key = api_key(llm::MISTRAL_KEY_ENTRY);
+
+        Analyzer {
+            client: reqwest::Client::new(),
+            retry: retry::load_policy(settings),
+            provider: llm::build_provider(&profile, llm_key.as_deref().unwrap_or_default()),
+            ocr: ocr::build_backend(&ocr_settings, &mistral_key),
+            extractors: pdf_text::build_extractors(settings, pdftotext),
+            corrections: Corrections::load(corrections),
+            catalog: Catalog::load(corrections),
+            document_db: documents::db_path(settings),
+            batch_id: None,
+            filenames: FilenameSchema::load(settings),
+        }
+    }
+
+    pub fn with_batch(mut self, batch_id: &str) -> Analyzer {
+        self.batch_id = Some(batch_id.to_string());
+        self
+    }
+
+    pub async fn analyze(
+        &self,
+        path: &str,
+        doc_type: &str,
+        refresh: bool,
+    ) -> Result<AnalysisResult, String> {
+        self.analyze_with_progress(path, doc_type, refresh, &|_| {})
+            .await
+    }
+
+    pub async fn analyze_with_progress(
+        &self,
+        path: &str,
+        doc_type: &str,
+        refresh: bool,
+        progress: &(dyn Fn(AnalysisStage) + Send + Sync),
+    ) -> Result<AnalysisResult, String> {
+        let hash = documents::hash_file(Path::new(path))?;
+        let meta = self.filenames.parse(Path::new(path));
+        if !refresh {
+            if let Some(cached) = self
+                .with_db(|db| db.cached_result(&hash, doc_type))
+                .flatten()
+            {
+                let extraction = cached.uncorrected.map_or(cached.extraction, |raw| *raw);
+                let mut result = self.finish(extraction, &meta)?;
+                result.document = cached.document;
+                result.classification = cached.classification;
+                result.mismatches = mismatches(&meta, doc_type, &result);
+                return Ok(result);
+            }
+        }
+
+        progress(AnalysisStage::Extracting);
+        let http = Http::new(self.client.clone(), self.retry);
+        let mut source = SourceText::default();
+        let is_fattura_pa = fattura_pa::is_candidate(Path::new(path));
+        let unwrapped = if is_fattura_pa {
+            None
+        } else {
+            fattura_pa::signed_pdf(Path::new(path))
+                .map(|pdf| unwrap_signed_pdf(&hash, pdf))
+                .transpose()?
+        };
+        let pdf_path = unwrapped
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string());
+
+        let (extraction, classification) = if is_fattura_pa {
+            let extraction = fattura_pa::parse_file(Path::new(path));
+            let classification = extraction
+                .as_ref()
+                .ok()
+                .map(|e| Classification::certain(e.kind()));
+            (extraction, classification)
+        } else if let Some(invoice) = self.embedded_invoice(&pdf_path, doc_type).await {
+            let classification = Classification::certain(invoice.kind());
+            (Ok(invoice), Some(classification))
+        } else {
+            let extraction = self
+                .extract_with_llm(&http, &pdf_path, doc_type, &mut source, progress)
+                .await;
+            self.record_usage(&hash, meta.lieferant.as_deref(), &http);
+            let classification = source
+                .ocr_text
+                .as_deref()
+                .or(source.text.as_deref())
+                .and_then(classify::classify);
+            (extraction, classification)
+        };
+        if let Some(tmp) = &unwrapped {
+            let _ = fs::remove_file(tmp);
+        }
+
+        let uncorrected = extraction.as_ref().ok().cloned().map(Box::new);
+        let mut result = extraction
+            .and_then(|e| self.finish(e, &meta))
+            .map_err(|mut e| {
+                if http.retries() > 0 {
+                    e = format!("{} ({} nuovi tentativi)", e, http.retries());
+                }
+                match classification.and_then(|c| c.mismatch(doc_type)) {
+                    Some(mismatch) => format!("{}. {}", e, mismatch),
+                    None => e,
+                }
+            })?;
+        result.retries = http.retries();
+        result.classification = classification;
+        result.mismatches = mismatches(&meta, doc_type, &result);
+        result.uncorrected = uncorrected;
+        result.document =
+            self.with_db(|db| db.save_analysis(&hash, Path::new(path), doc_type, &source, &result));
+        result.uncorrected = None;
+        Ok(result)
+    }
+
+    fn record_usage(&self, hash: &str, supplier: Option<&str>, http: &Http) {
+        let entries = http.take_usage();
+        if entries.is_empty() {
+            return;
+        }
+        self.with_db(|db| db.record_usage(hash, self.batch_id.as_deref(), supplier, &entries));
+    }
+
+    fn with_db<T>(&self, f: impl FnOnce(&DocumentDb) -> Result<T, String>) -> Option<T> {
+        let path = self.document_db.as_ref()?;
+        match DocumentDb::open(path).and_then(|db| f(&db)) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                println!("Archivio documenti: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn embedded_invoice(&self, path: &str, doc_type: &str) -> Option<Extraction> {
+        if doc_type != "rechnung" && doc_type != "gutschrift" {
+            return None;
+        }
+
+        let path = path.to_string();
+        let result = tauri::async_runtime::spawn_blocking(move || factur_x::extract(&path))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+
+        match result {
+            Ok(invoice) => invoice.filter(|i| i.has_products()),
+            Err(e) => {
+                println!("Fattura XML incorporata non leggibile: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn extract_with_llm(
+        &self,
+        http: &Http,
+        path: &str,
+        doc_type: &str,
+        source: &mut SourceText,
+        progress: &(dyn Fn(AnalysisStage) + Send + Sync),
+    ) -> Result<Extraction, String> {
+        let mut extracted_text = pdf_text::extract_text(&self.extractors, path, TextMode::Plain)
+            .await
+            .unwrap_or_default();
+        source.text = Some(extracted_text.clone()).filter(|t| !t.trim().is_empty());
+        let mut layout_instruction = ocr::WHITESPACE_LAYOUT;
+        let mut used_ocr = false;
+
+        if extracted_text.trim().len() < pdf_text::MIN_TEXT_LEN {
+            progress(AnalysisStage::Ocr);
+            match http
+                .tagged(UsageStep::Primary, self.ocr.recognize(http, path))
+                .await
+            {
+                Ok(text) => {
+                    source.ocr_text = Some(text.clone());
+                    extracted_text = text;
+                    used_ocr = true;
+                    layout_instruction = self.ocr.layout_instruction();
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "Errore critico: impossibile convertire il testo in PDF o eseguire l'OCR. ({})",
+                        e
+                    ));
+                }
+            }
+        }
+
+        let base_prompt = match doc_type {
+            "rechnung" => PROMPT_RECHNUNG,
+            "lieferschein" => PROMPT_LIEFERSCHEIN,
+            "gutschrift" => PROMPT_GUTSCHRIFT,
+            _ => PROMPT_AUFTRAG,
+        };
+
+        let products_non_empty = |r: &Result<Extraction, String>| -> bool {
+            matches!(r, Ok(extraction) if extraction.has_products())
+        };
+
+        let full_prompt = format!(
+            "{}\n\nWICHTIGE LAYOUT-INFO: {}\n\nDokument Inhalt:\n{}",
+            base_prompt, layout_instruction, extracted_text
+        );
+
+        progress(AnalysisStage::Llm);
+        let mut result = Extraction::parse(
+            doc_type,
+            http.tagged(
+                UsageStep::Primary,
+                self.provider.complete_json(http, &full_prompt),
+            )
+            .await?,
+        );
+
+        if !products_non_empty(&result) {
+            if !used_ocr {
+                if let Ok(layout_text) =
+                    pdf_text::extract_text(&self.extractors, path, TextMode::Layout).await
+                {
+                    if layout_text.trim().len() > pdf_text::MIN_TEXT_LEN {
+                        let retry_prompt = format!(
+                            "{}\n\nWICHTIGE LAYOUT-INFO: THE LAYOUT IS LAYOUT. Preserve original PDF layout.\n\nDokument Inhalt:\n{}",
+                            base_prompt, layout_text
+                        );
+
+                        progress(AnalysisStage::Llm);
+                        if let Ok(value) = http
+                            .tagged(
+                                UsageStep::LayoutRetry,
+                                self.provider.complete_json(http, &retry_prompt),
+                            )
+                            .await
+                        {
+                            let parsed = Extraction::parse(doc_type, value);
+                            if products_non_empty(&parsed) {
+                                result = parsed;
+                            }
+                        }
+                    }
+                }
+
+                if !products_non_empty(&result) {
+                    progress(AnalysisStage::Ocr);
+                    match http
+                        .tagged(UsageStep::OcrFallback, self.ocr.recognize(http, path))
+                        .await
+                    {
+                        Ok(ocr_text) => {
+                            source.ocr_text = Some(ocr_text.clone());
+                            let retry_prompt = format!(
+                                "{}\n\nWICHTIGE LAYOUT-INFO: {}\n\nDokument Inhalt:\n{}",
+                                base_prompt,
+                                self.ocr.layout_instruction(),
+                                ocr_text
+                            );
+                            progress(AnalysisStage::Llm);
+                            if let Ok(value) = http
+                                .tagged(
+                                    UsageStep::OcrFallback,
+                                    self.provider.complete_json(http, &retry_prompt),
+                                )
+                                .await
+                            {
+                                result = Extraction::parse(doc_type, value);
+                            }
+                        }
+                        Err(e) => {
+                            println!("Fallback OCR non riuscito: {}", e);
+                        }
+                    }
+                }
+            } else {
+                sleep(Duration::from_millis(1500)).await;
+            }
+        }
+
+        result
+    }
+
+    fn finish(
+        &self,
+        mut extraction: Extraction,
+        meta: &FileMetadata,
+    ) -> Result<AnalysisResult, String> {
+        if !extraction.has_products() {
+            return Err("Nessun prodotto riconosciuto nel documento.".to_string());
+        }
+
+        let corrections = self.corrections.apply(&mut extraction, meta);
+        let catalog = self.catalog.apply(&mut extraction, meta);
+        let mut result = AnalysisResult::from(extraction);
+        result.corrections = corrections;
+        result.catalog = catalog;
+        Ok(result)
+    }
+}
+
+fn unwrap_signed_pdf(hash: &str, pdf: Vec<u8>) -> Result<PathBuf, String> {
+    let path = std::env::temp_dir().join(format!("maggus-{}.pdf", hash));
+    fs::write(&path, pdf).map_err(|e| format!("Impossibile estrarre il PDF firmato: {}", e))?;
+    Ok(path)
+}
+
+fn mismatches(meta: &FileMetadata, doc_type: &str, result: &AnalysisResult) -> Vec<String> {
+    let mut mismatches = filename::cross_check(meta, &result.extraction);
+    mismatches.extend(result.classification.and_then(|c| c.mismatch(doc_type)));
+    mismatches
+}
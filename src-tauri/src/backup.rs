@@ -0,0 +1,187 @@
+use chrono::Local;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::column_map::ColumnMapping;
+use crate::excel::{self, ExportDiff, ExportOptions, ExportRow};
+use crate::settings::{self, Settings};
+
+const JOURNAL_FILE: &str = "journal.json";
+const DEFAULT_RETENTION: usize = 50;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum JournalKind {
+    Export,
+    Restore,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub id: String,
+    pub timestamp: String,
+    pub kind: JournalKind,
+    pub workbook: String,
+    pub backup: String,
+    pub updated: usize,
+    pub inserted: usize,
+    pub restored_from: Option<String>,
+    pub restored_at: Option<String>,
+}
+
+pub struct BackupStore {
+    dir: PathBuf,
+    retention: usize,
+}
+
+impl BackupStore {
+    pub fn from_settings(settings: &Settings) -> Result<BackupStore, String> {
+        let dir = settings
+            .get("backupDir")
+            .and_then(|v| v.as_str())
+            .filter(|d| !d.trim().is_empty())
+            .map(PathBuf::from)
+            .or_else(|| settings::default_store_path("backups"))
+            .ok_or("Cartella di backup non disponibile.".to_string())?;
+        let retention = settings
+            .get("backupRetention")
+            .and_then(|v| v.as_u64())
+            .map(|n| n as usize)
+            .unwrap_or(DEFAULT_RETENTION);
+
+        Ok(BackupStore { dir, retention })
+    }
+
+    pub fn journal(&self) -> Result<Vec<JournalEntry>, String> {
+        let path = self.dir.join(JOURNAL_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Impossibile leggere il giornale: {}", e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Giornale non valido: {}", e))
+    }
+
+    fn save_journal(&self, journal: &[JournalEntry]) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(journal).map_err(|e| e.to_string())?;
+        fs::write(self.dir.join(JOURNAL_FILE), content)
+            .map_err(|e| format!("Impossibile salvare il giornale: {}", e))
+    }
+
+    fn snapshot(&self, workbook: &Path, kind: JournalKind) -> Result<JournalEntry, String> {
+        let now = Local::now();
+        let id = now.format("%Y%m%d-%H%M%S-%3f").to_string();
+        let version_dir = self.dir.join(&id);
+        fs::create_dir_all(&version_dir)
+            .map_err(|e| format!("Impossibile creare la cartella di backup: {}", e))?;
+
+        let file_name = workbook
+            .file_name()
+            .ok_or("Nome file Excel non valido.".to_string())?;
+        let backup = version_dir.join(file_name);
+        fs::copy(workbook, &backup).map_err(|e| format!("Backup non riuscito: {}", e))?;
+
+        Ok(JournalEntry {
+            id,
+            timestamp: now.to_rfc3339(),
+            kind,
+            workbook: workbook.to_string_lossy().to_string(),
+            backup: backup.to_string_lossy().to_string(),
+            updated: 0,
+            inserted: 0,
+            restored_from: None,
+            restored_at: None,
+        })
+    }
+
+    fn discard(&self, entry: &JournalEntry) {
+        let _ = fs::remove_dir_all(self.dir.join(&entry.id));
+    }
+
+    fn record(&self, entry: JournalEntry) -> Result<(), String> {
+        let mut journal = self.journal()?;
+        journal.push(entry);
+
+        let excess = journal.len().saturating_sub(self.retention.max(1));
+        for old in journal.drain(..excess) {
+            self.discard(&old);
+        }
+
+        self.save_journal(&journal)
+    }
+
+    pub fn export(
+        &self,
+        path: &Path,
+        data: Vec<ExportRow>,
+        mapping: &ColumnMapping,
+        options: &ExportOptions,
+        progress: &dyn Fn(usize, usize),
+    ) -> Result<ExportDiff, String> {
+        excel::ensure_writable(path)?;
+        let mut entry = self.snapshot(path, JournalKind::Export)?;
+
+        match excel::export_rows(
+            path,
+            data,
+            mapping,
+            &ExportOptions {
+                dry_run: false,
+                ..options.clone()
+            },
+            progress,
+        ) {
+            Ok(diff) => {
+                entry.updated = diff.updates.len();
+                entry.inserted = diff.insertions.len();
+                self.record(entry)?;
+                Ok(diff)
+            }
+            Err(e) => {
+                self.discard(&entry);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn restore(&self, id: &str) -> Result<JournalEntry, String> {
+        let mut journal = self.journal()?;
+        let target = journal
+            .iter()
+            .find(|e| e.id == id)
+            .cloned()
+            .ok_or_else(|| format!("Esportazione sconosciuta: {}", id))?;
+
+        let workbook = PathBuf::from(&target.workbook);
+        let backup = PathBuf::from(&target.backup);
+        if !backup.exists() {
+            return Err(format!("Backup non trovato: {}", backup.display()));
+        }
+        excel::ensure_writable(&workbook)?;
+
+        let mut undo = self.snapshot(&workbook, JournalKind::Restore)?;
+        let restored = excel::replace_atomic(&workbook, |file| {
+            let mut source = fs::File::open(&backup).map_err(|e| e.to_string())?;
+            io::copy(&mut source, file)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = restored {
+            self.discard(&undo);
+            return Err(format!("Ripristino non riuscito: {}", e));
+        }
+
+        undo.restored_from = Some(target.id.clone());
+        let restored_at = undo.timestamp.clone();
+        if let Some(entry) = journal.iter_mut().find(|e| e.id == id) {
+            entry.restored_at = Some(restored_at);
+        }
+        self.save_journal(&journal)?;
+        self.record(undo.clone())?;
+
+        Ok(undo)
+    }
+}
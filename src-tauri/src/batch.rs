@@ -0,0 +1,244 @@
+use chrono::Local;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use crate::analysis::{AnalysisStage, Analyzer};
+use crate::extraction::AnalysisResult;
+use crate::settings::Settings;
+
+const DEFAULT_CONCURRENCY: usize = 5;
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchDocument {
+    pub path: String,
+    pub doc_type: String,
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Extracting,
+    Ocr,
+    Llm,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl From<AnalysisStage> for JobStatus {
+    fn from(stage: AnalysisStage) -> Self {
+        match stage {
+            AnalysisStage::Extracting => JobStatus::Extracting,
+            AnalysisStage::Ocr => JobStatus::Ocr,
+            AnalysisStage::Llm => JobStatus::Llm,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JobEvent<'a> {
+    batch_id: &'a str,
+    index: usize,
+    path: &'a str,
+    status: JobStatus,
+    result: Option<AnalysisResult>,
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchSummary {
+    batch_id: String,
+    done: usize,
+    failed: usize,
+    cancelled: bool,
+}
+
+struct Job {
+    app: AppHandle,
+    batch_id: String,
+    index: usize,
+    path: String,
+}
+
+impl Job {
+    fn emit(&self, status: JobStatus, result: Option<AnalysisResult>, error: Option<String>) {
+        let _ = self.app.emit(
+            "batch-status",
+            JobEvent {
+                batch_id: &self.batch_id,
+                index: self.index,
+                path: &self.path,
+                status,
+                result,
+                error,
+            },
+        );
+    }
+}
+
+pub struct Batches {
+    session: String,
+    next_id: AtomicU64,
+    running: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl Default for Batches {
+    fn default() -> Self {
+        // Batch ids end up in the usage table, so they must stay unique across app launches.
+        Batches {
+            session: Local::now().format("%Y%m%d-%H%M%S").to_string(),
+            next_id: AtomicU64::new(0),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Batches {
+    pub fn start(
+        &self,
+        app: AppHandle,
+        analyzer: Analyzer,
+        documents: Vec<BatchDocument>,
+        refresh: bool,
+        concurrency: usize,
+    ) -> String {
+        let batch_id = format!(
+            "batch-{}-{}",
+            self.session,
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let token = CancellationToken::new();
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(batch_id.clone(), token.clone());
+        }
+
+        tauri::async_runtime::spawn(run(
+            app,
+            batch_id.clone(),
+            token,
+            Arc::new(analyzer.with_batch(&batch_id)),
+            documents,
+            refresh,
+            concurrency,
+        ));
+        batch_id
+    }
+
+    pub fn cancel(&self, batch_id: &str) -> bool {
+        match self
+            .running
+            .lock()
+            .ok()
+            .and_then(|r| r.get(batch_id).cloned())
+        {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish(&self, batch_id: &str) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(batch_id);
+        }
+    }
+}
+
+pub fn concurrency(settings: &Settings) -> usize {
+    settings
+        .get("batchConcurrency")
+        .and_then(|v| v.as_u64())
+        .map(|n| n as usize)
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .max(1)
+}
+
+async fn run(
+    app: AppHandle,
+    batch_id: String,
+    token: CancellationToken,
+    analyzer: Arc<Analyzer>,
+    documents: Vec<BatchDocument>,
+    refresh: bool,
+    concurrency: usize,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = Vec::with_capacity(documents.len());
+
+    for (index, document) in documents.into_iter().enumerate() {
+        let job = Job {
+            app: app.clone(),
+            batch_id: batch_id.clone(),
+            index,
+            path: document.path.clone(),
+        };
+        job.emit(JobStatus::Queued, None, None);
+
+        let token = token.clone();
+        let analyzer = analyzer.clone();
+        let semaphore = semaphore.clone();
+        tasks.push(tauri::async_runtime::spawn(async move {
+            let outcome = token
+                .run_until_cancelled(async {
+                    let _permit = semaphore
+                        .acquire()
+                        .await
+                        .map_err(|e| format!("Coda interrotta: {}", e))?;
+                    analyzer
+                        .analyze_with_progress(
+                            &document.path,
+                            &document.doc_type,
+                            refresh,
+                            &|stage| job.emit(stage.into(), None, None),
+                        )
+                        .await
+                })
+                .await;
+
+            match outcome {
+                Some(Ok(result)) => {
+                    job.emit(JobStatus::Done, Some(result), None);
+                    JobStatus::Done
+                }
+                Some(Err(e)) => {
+                    job.emit(JobStatus::Failed, None, Some(e));
+                    JobStatus::Failed
+                }
+                None => {
+                    job.emit(JobStatus::Cancelled, None, None);
+                    JobStatus::Cancelled
+                }
+            }
+        }));
+    }
+
+    let (mut done, mut failed) = (0, 0);
+    for task in tasks {
+        match task.await {
+            Ok(JobStatus::Done) => done += 1,
+            Ok(JobStatus::Failed) => failed += 1,
+            _ => {}
+        }
+    }
+
+    let _ = app.emit(
+        "batch-finished",
+        BatchSummary {
+            batch_id: batch_id.clone(),
+            done,
+            failed,
+            cancelled: token.is_cancelled(),
+        },
+    );
+    app.state::<Batches>().finish(&batch_id);
+}
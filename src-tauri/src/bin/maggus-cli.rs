@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    maggus_lib::run_cli()
+}
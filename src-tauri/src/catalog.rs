@@ -0,0 +1,346 @@
+use serde_json::json;
+use std::collections::HashMap;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use crate::excel::ExportRow;
+use crate::extraction::Extraction;
+use crate::filename::FileMetadata;
+use crate::product_match::normalize;
+use crate::settings::Settings;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArticleCode {
+    pub lieferant: String,
+    pub code: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub unit: Option<String>,
+    pub codes: Vec<ArticleCode>,
+    pub synonyms: Vec<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum CatalogMatchKind {
+    Code,
+    Name,
+    Synonym,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogMatch {
+    pub produkt: String,
+    pub before: String,
+    pub einheit: Option<String>,
+    pub via: CatalogMatchKind,
+    pub explanation: String,
+}
+
+#[derive(Clone, Default)]
+pub struct Catalog {
+    entries: Vec<CatalogEntry>,
+    names: HashMap<String, (usize, CatalogMatchKind)>,
+}
+
+fn supplier_matches(code: &ArticleCode, lieferant: Option<&str>) -> bool {
+    let scope = normalize(&code.lieferant);
+    scope.is_empty() || lieferant.is_some_and(|l| normalize(l).contains(&scope))
+}
+
+impl Catalog {
+    pub fn new(entries: Vec<CatalogEntry>) -> Result<Catalog, String> {
+        let mut names: HashMap<String, (usize, CatalogMatchKind)> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.name.trim().is_empty() {
+                return Err("Il nome del prodotto in catalogo non può essere vuoto.".to_string());
+            }
+            if entry.codes.iter().any(|c| c.code.trim().is_empty()) {
+                return Err(format!("Catalogo «{}»: codice articolo vuoto", entry.name));
+            }
+
+            let keys = std::iter::once((&entry.name, CatalogMatchKind::Name)).chain(
+                entry
+                    .synonyms
+                    .iter()
+                    .map(|s| (s, CatalogMatchKind::Synonym)),
+            );
+            for (key, kind) in keys {
+                let key = normalize(key);
+                if key.is_empty() {
+                    continue;
+                }
+                match names.get(&key) {
+                    Some(&(other, _)) if other != i => {
+                        return Err(format!(
+                            "Catalogo: «{}» è associato sia a «{}» sia a «{}»",
+                            key, entries[other].name, entry.name
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        names.insert(key, (i, kind));
+                    }
+                }
+            }
+        }
+
+        Ok(Catalog { entries, names })
+    }
+
+    pub fn load(corrections: &Settings) -> Catalog {
+        Catalog::new(load_entries(corrections)).unwrap_or_else(|e| {
+            println!("Catalogo prodotti ignorato: {}", e);
+            Catalog::default()
+        })
+    }
+
+    fn resolve(
+        &self,
+        produkt: &str,
+        code: Option<&str>,
+        lieferant: Option<&str>,
+    ) -> Option<(&CatalogEntry, CatalogMatchKind)> {
+        // A code listed for the supplier wins over the same code listed for everyone.
+        let by_code = code
+            .map(normalize)
+            .filter(|c| !c.is_empty())
+            .and_then(|code| {
+                let find = |scoped: bool| {
+                    self.entries.iter().find(|e| {
+                        e.codes.iter().any(|c| {
+                            normalize(&c.code) == code
+                                && normalize(&c.lieferant).is_empty() != scoped
+                                && supplier_matches(c, lieferant)
+                        })
+                    })
+                };
+                find(true).or_else(|| find(false))
+            });
+        if let Some(entry) = by_code {
+            return Some((entry, CatalogMatchKind::Code));
+        }
+
+        self.names
+            .get(&normalize(produkt))
+            .map(|&(i, kind)| (&self.entries[i], kind))
+    }
+
+    pub fn apply(&self, extraction: &mut Extraction, meta: &FileMetadata) -> Vec<CatalogMatch> {
+        let (lieferant, _) = extraction.parties_mut();
+        let lieferant = lieferant.clone().or_else(|| meta.lieferant.clone());
+
+        let mut matches = Vec::new();
+        for (produkt, code) in extraction.products_mut() {
+            let Some((entry, via)) = self.resolve(produkt, code, lieferant.as_deref()) else {
+                continue;
+            };
+            let explanation = match via {
+                CatalogMatchKind::Code => format!(
+                    "«{}» → «{}»: catalogo, codice articolo {}",
+                    produkt,
+                    entry.name,
+                    code.unwrap_or_default()
+                ),
+                CatalogMatchKind::Name => format!("«{}»: catalogo", entry.name),
+                CatalogMatchKind::Synonym => {
+                    format!("«{}» → «{}»: catalogo, sinonimo", produkt, entry.name)
+                }
+            };
+            let before = std::mem::replace(produkt, entry.name.clone());
+            matches.push(CatalogMatch {
+                produkt: entry.name.clone(),
+                before,
+                einheit: entry.unit.clone(),
+                via,
+                explanation,
+            });
+        }
+        matches
+    }
+
+    pub fn canonical_name(&self, produkt: &str) -> Option<&str> {
+        self.names
+            .get(&normalize(produkt))
+            .map(|&(i, _)| self.entries[i].name.as_str())
+    }
+
+    pub fn canonicalize(&self, row: &mut ExportRow) {
+        let Some(produkt) = row.produkt.as_deref() else {
+            return;
+        };
+        let Some((entry, _)) = self.resolve(
+            produkt,
+            row.artikelnummer.as_deref(),
+            row.lieferant.as_deref(),
+        ) else {
+            return;
+        };
+        row.produkt = Some(entry.name.clone());
+        if row.einheit.is_none() {
+            row.einheit = entry.unit.clone();
+        }
+    }
+}
+
+pub fn load_entries(corrections: &Settings) -> Vec<CatalogEntry> {
+    corrections.get_as("product_catalog").unwrap_or_default()
+}
+
+pub fn save_entries(app: &AppHandle, entries: &[CatalogEntry]) -> Result<(), String> {
+    Catalog::new(entries.to_vec())?;
+
+    let store = app
+        .store("corrections.json")
+        .map_err(|e| format!("Store errore: {}", e))?;
+
+    store.set("product_catalog", json!(entries));
+    store
+        .save()
+        .map_err(|e| format!("Errore di memoria: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, codes: &[(&str, &str)], synonyms: &[&str]) -> CatalogEntry {
+        CatalogEntry {
+            name: name.to_string(),
+            unit: Some("kg".to_string()),
+            codes: codes
+                .iter()
+                .map(|(lieferant, code)| ArticleCode {
+                    lieferant: lieferant.to_string(),
+                    code: code.to_string(),
+                })
+                .collect(),
+            synonyms: synonyms.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn canonical(catalog: &Catalog, produkt: &str, code: &str, lieferant: &str) -> String {
+        let mut row = ExportRow {
+            produkt: Some(produkt.to_string()),
+            artikelnummer: Some(code.to_string()).filter(|c| !c.is_empty()),
+            lieferant: Some(lieferant.to_string()).filter(|l| !l.is_empty()),
+            ..Default::default()
+        };
+        catalog.canonicalize(&mut row);
+        row.produkt.unwrap()
+    }
+
+    #[test]
+    fn supplier_article_code_names_the_product() {
+        let catalog = Catalog::new(vec![entry(
+            "CARDO MARIANO SEMI",
+            &[("Müller", "4711")],
+            &[],
+        )])
+        .unwrap();
+
+        let mut row = ExportRow {
+            produkt: Some("Semi cardo 500 g".to_string()),
+            artikelnummer: Some("4711".to_string()),
+            lieferant: Some("Müller GmbH".to_string()),
+            ..Default::default()
+        };
+        catalog.canonicalize(&mut row);
+        assert_eq!(row.produkt.as_deref(), Some("CARDO MARIANO SEMI"));
+        assert_eq!(row.einheit.as_deref(), Some("kg"));
+
+        assert_eq!(
+            canonical(&catalog, "Semi cardo 500 g", "4711", "Rossi"),
+            "Semi cardo 500 g"
+        );
+        assert_eq!(
+            canonical(&catalog, "Semi cardo 500 g", "4711", ""),
+            "Semi cardo 500 g"
+        );
+    }
+
+    #[test]
+    fn global_codes_apply_to_every_supplier_unless_one_has_its_own() {
+        let catalog = Catalog::new(vec![
+            entry("VITE M8", &[("", "100")], &[]),
+            entry("TASSELLO 10", &[("Müller", "100")], &[]),
+        ])
+        .unwrap();
+        assert_eq!(canonical(&catalog, "x", "100", "Rossi"), "VITE M8");
+        assert_eq!(canonical(&catalog, "x", "100", ""), "VITE M8");
+        assert_eq!(canonical(&catalog, "x", "100", "Müller"), "TASSELLO 10");
+    }
+
+    #[test]
+    fn code_takes_precedence_over_name_and_synonyms() {
+        let catalog = Catalog::new(vec![
+            entry(
+                "CARDO MARIANO SEMI",
+                &[("Müller", "4711")],
+                &["semi di cardo"],
+            ),
+            entry("OLIO ROSMARINO", &[], &[]),
+        ])
+        .unwrap();
+        assert_eq!(
+            canonical(&catalog, "Olio rosmarino", "4711", "Müller"),
+            "CARDO MARIANO SEMI"
+        );
+        assert_eq!(
+            canonical(&catalog, "Olio  Rosmarino", "", ""),
+            "OLIO ROSMARINO"
+        );
+        assert_eq!(
+            canonical(&catalog, "Semi di cardo", "9999", "Müller"),
+            "CARDO MARIANO SEMI"
+        );
+    }
+
+    #[test]
+    fn synonyms_shared_between_products_are_rejected() {
+        let error = Catalog::new(vec![
+            entry("CARDO MARIANO SEMI", &[], &["cardo"]),
+            entry("CARDO MARIANO OLIO", &[], &["Cardo"]),
+        ])
+        .err()
+        .unwrap();
+        assert!(error.contains("CARDO MARIANO SEMI") && error.contains("CARDO MARIANO OLIO"));
+
+        assert!(Catalog::new(vec![entry("CARDO", &[], &["cardo"])]).is_ok());
+        assert!(Catalog::new(vec![entry("CARDO", &[("Müller", " ")], &[])]).is_err());
+    }
+
+    #[test]
+    fn apply_explains_each_match() {
+        let catalog = Catalog::new(vec![entry(
+            "CARDO MARIANO SEMI",
+            &[("Müller", "4711")],
+            &["semi di cardo"],
+        )])
+        .unwrap();
+        let mut extraction: Extraction = serde_json::from_value(json!({
+            "docType": "rechnung",
+            "produkte": [
+                { "produkt": "Semi di cardo", "artikelnummer": null },
+                { "produkt": "Cardo bio", "artikelnummer": "4711" }
+            ]
+        }))
+        .unwrap();
+        let meta = FileMetadata {
+            lieferant: Some("Müller".to_string()),
+            ..Default::default()
+        };
+
+        let matches = catalog.apply(&mut extraction, &meta);
+        let via: Vec<CatalogMatchKind> = matches.iter().map(|m| m.via).collect();
+        assert_eq!(via, [CatalogMatchKind::Synonym, CatalogMatchKind::Code]);
+        assert_eq!(matches[1].before, "Cardo bio");
+        assert!(matches[1].explanation.contains("codice articolo 4711"));
+    }
+}
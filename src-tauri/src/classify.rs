@@ -0,0 +1,244 @@
+pub const MIN_CONFIDENCE: f64 = 0.6;
+
+const HEADER_LEN: usize = 800;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum DocumentKind {
+    Auftrag,
+    Rechnung,
+    Gutschrift,
+    Lieferschein,
+    Proforma,
+}
+
+impl DocumentKind {
+    const ALL: [DocumentKind; 5] = [
+        DocumentKind::Auftrag,
+        DocumentKind::Rechnung,
+        DocumentKind::Gutschrift,
+        DocumentKind::Lieferschein,
+        DocumentKind::Proforma,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DocumentKind::Auftrag => "conferma d'ordine",
+            DocumentKind::Rechnung => "fattura",
+            DocumentKind::Gutschrift => "nota di credito",
+            DocumentKind::Lieferschein => "documento di trasporto",
+            DocumentKind::Proforma => "fattura proforma",
+        }
+    }
+
+    pub fn from_doc_type(doc_type: &str) -> DocumentKind {
+        match doc_type {
+            "rechnung" => DocumentKind::Rechnung,
+            "lieferschein" => DocumentKind::Lieferschein,
+            "gutschrift" => DocumentKind::Gutschrift,
+            _ => DocumentKind::Auftrag,
+        }
+    }
+
+    fn keywords(&self) -> &'static [(&'static str, f64)] {
+        match self {
+            DocumentKind::Auftrag => &[
+                ("conferma d'ordine", 3.0),
+                ("conferma ordine", 3.0),
+                ("conferma di vendita", 3.0),
+                ("auftragsbestätigung", 3.0),
+                ("auftragsbestaetigung", 3.0),
+                ("bestellbestätigung", 3.0),
+                ("order confirmation", 3.0),
+                ("sales order", 2.0),
+                ("bestellung", 1.0),
+                ("ordine", 1.0),
+            ],
+            DocumentKind::Rechnung => &[
+                ("fattura", 2.0),
+                ("rechnung", 2.0),
+                ("invoice", 2.0),
+                ("facture", 2.0),
+                ("factura", 2.0),
+            ],
+            DocumentKind::Gutschrift => &[
+                ("nota di credito", 3.0),
+                ("nota credito", 3.0),
+                ("gutschrift", 3.0),
+                ("rechnungskorrektur", 3.0),
+                ("credit note", 3.0),
+                ("credit memo", 3.0),
+                ("avoir", 2.0),
+            ],
+            DocumentKind::Lieferschein => &[
+                ("documento di trasporto", 3.0),
+                ("documento di consegna", 3.0),
+                ("bolla di consegna", 3.0),
+                ("lieferschein", 3.0),
+                ("delivery note", 3.0),
+                ("packing list", 2.0),
+                ("d.d.t.", 1.0),
+                ("ddt", 1.0),
+            ],
+            DocumentKind::Proforma => &[("proforma", 3.0), ("pro-forma", 3.0), ("pro forma", 3.0)],
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Classification {
+    pub kind: DocumentKind,
+    pub confidence: f64,
+}
+
+impl Classification {
+    pub fn certain(kind: DocumentKind) -> Classification {
+        Classification {
+            kind,
+            confidence: 1.0,
+        }
+    }
+
+    pub fn mismatch(&self, doc_type: &str) -> Option<String> {
+        let expected = DocumentKind::from_doc_type(doc_type);
+        if self.kind == expected || self.confidence < MIN_CONFIDENCE {
+            return None;
+        }
+        Some(format!(
+            "Tipo documento: nome file «{}», contenuto «{}» ({:.0}%)",
+            expected.label(),
+            self.kind.label(),
+            self.confidence * 100.0
+        ))
+    }
+}
+
+fn first_word_match(text: &str, keyword: &str) -> Option<usize> {
+    text.match_indices(keyword).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + keyword.len()..].chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric()) && !after.is_some_and(|c| c.is_alphanumeric())
+    })
+}
+
+pub fn classify(text: &str) -> Option<Classification> {
+    let text = text.to_lowercase();
+    let scores: Vec<(DocumentKind, f64)> = DocumentKind::ALL
+        .iter()
+        .map(|kind| {
+            let score = kind
+                .keywords()
+                .iter()
+                .filter_map(|(keyword, weight)| {
+                    first_word_match(&text, keyword).map(|pos| {
+                        if pos < HEADER_LEN {
+                            weight * 2.0
+                        } else {
+                            *weight
+                        }
+                    })
+                })
+                .sum();
+            (*kind, score)
+        })
+        .collect();
+
+    let total: f64 = scores.iter().map(|(_, s)| s).sum();
+    let (kind, best) = scores.into_iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best <= 0.0 {
+        return None;
+    }
+
+    Some(Classification {
+        kind,
+        confidence: best / total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(header: &str, footer: &str) -> String {
+        format!(
+            "{}\n{}\n{}",
+            header,
+            "riga articolo 1 pz\n".repeat(60),
+            footer
+        )
+    }
+
+    #[test]
+    fn header_keywords_outweigh_the_body() {
+        let text = body(
+            "DOCUMENTO DI TRASPORTO N. 33 del 12/03/2026\nCausale: vendita",
+            "Eventuali resi con nota di credito",
+        );
+        let classification = classify(&text).unwrap();
+        assert_eq!(classification.kind, DocumentKind::Lieferschein);
+        assert!((classification.confidence - 6.0 / 9.0).abs() < 1e-9);
+
+        assert!(classify("Grazie per la collaborazione").is_none());
+    }
+
+    #[test]
+    fn proforma_invoices_are_not_invoices() {
+        let text = body(
+            "FATTURA PROFORMA N. 12 del 02/03/2026",
+            "Totale EUR 1.250,00",
+        );
+        let classification = classify(&text).unwrap();
+        assert_eq!(classification.kind, DocumentKind::Proforma);
+        assert!(classification.mismatch("rechnung").is_some());
+    }
+
+    #[test]
+    fn credit_notes_citing_an_invoice_stay_credit_notes() {
+        let text = body(
+            "NOTA DI CREDITO N. 7 del 20/03/2026\nA storno della fattura n. 118 del 05/03/2026",
+            "",
+        );
+        let classification = classify(&text).unwrap();
+        assert_eq!(classification.kind, DocumentKind::Gutschrift);
+        assert!(classification.confidence >= MIN_CONFIDENCE);
+        assert!(classification.mismatch("gutschrift").is_none());
+        assert!(classification.mismatch("rechnung").is_some());
+    }
+
+    #[test]
+    fn invoices_citing_order_and_ddt_are_invoices_without_a_warning() {
+        let text = body(
+            "FATTURA N. 118 del 05/03/2026\nRif. ordine 4500123 - DDT n. 33 del 02/03/2026",
+            "",
+        );
+        let classification = classify(&text).unwrap();
+        assert_eq!(classification.kind, DocumentKind::Rechnung);
+        // Half of the score only: too uncertain to contradict the file name.
+        assert!(classification.confidence < MIN_CONFIDENCE);
+        assert!(classification.mismatch("auftrag").is_none());
+    }
+
+    #[test]
+    fn keywords_match_whole_words_only() {
+        assert!(classify("Il cliente ordinerà la merce").is_none());
+        assert!(classify("Fatturato annuo").is_none());
+    }
+
+    #[test]
+    fn mismatch_is_reported_from_the_minimum_confidence() {
+        let at = |confidence| Classification {
+            kind: DocumentKind::Rechnung,
+            confidence,
+        };
+        assert_eq!(
+            at(MIN_CONFIDENCE).mismatch("auftrag").as_deref(),
+            Some("Tipo documento: nome file «conferma d'ordine», contenuto «fattura» (60%)")
+        );
+        assert!(at(MIN_CONFIDENCE - 0.01).mismatch("auftrag").is_none());
+        assert!(at(1.0).mismatch("rechnung").is_none());
+        assert!(Classification::certain(DocumentKind::Lieferschein)
+            .mismatch("lieferschein")
+            .is_none());
+    }
+}
@@ -0,0 +1,430 @@
+use dotenv::dotenv;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::analysis::Analyzer;
+use crate::backup::BackupStore;
+use crate::catalog::Catalog;
+use crate::column_map;
+use crate::documents;
+use crate::excel::{self, ExportDiff, ExportOptions, ExportRow};
+use crate::extraction::{AnalysisResult, Extraction};
+use crate::fattura_pa;
+use crate::filename::FileMetadata;
+use crate::filename_schema::FilenameSchema;
+use crate::llm;
+use crate::pdf_text::SystemPdftotextExtractor;
+use crate::product_match;
+use crate::reconcile;
+use crate::settings::{self, Settings};
+
+const USAGE: &str = "Uso: maggus-cli <cartella-documenti> <file.xlsx> [--settings <settings.json>] [--corrections <corrections.json>] [--pdftotext <eseguibile>] [--dry-run]
+
+--dry-run  mostra le modifiche previste senza salvare il file Excel.
+
+La chiave API Mistral viene letta da MAGGUS_API_KEY, quella dei profili compatibili OpenAI da
+MAGGUS_LLM_API_KEY; in mancanza, dal portachiavi di sistema.";
+
+struct CliArgs {
+    pdf_dir: PathBuf,
+    workbook: PathBuf,
+    settings: PathBuf,
+    corrections: PathBuf,
+    pdftotext: String,
+    dry_run: bool,
+}
+
+fn parse_args(args: Vec<String>) -> Result<CliArgs, String> {
+    let mut positional = Vec::new();
+    let mut settings = settings::default_store_path("settings.json").unwrap_or_default();
+    let mut corrections = settings::default_store_path("corrections.json").unwrap_or_default();
+    let mut pdftotext = "pdftotext".to_string();
+    let mut dry_run = false;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| {
+            iter.next()
+                .ok_or_else(|| format!("Valore mancante per {}", name))
+        };
+        match arg.as_str() {
+            "--settings" => settings = PathBuf::from(value("--settings")?),
+            "--corrections" => corrections = PathBuf::from(value("--corrections")?),
+            "--pdftotext" => pdftotext = value("--pdftotext")?,
+            "--dry-run" => dry_run = true,
+            "-h" | "--help" => return Err(String::new()),
+            other if other.starts_with("--") => {
+                return Err(format!("Opzione sconosciuta: {}", other))
+            }
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err("Specificare la cartella PDF e il file Excel.".to_string());
+    }
+    let workbook = positional.pop().unwrap_or_default();
+    let pdf_dir = positional.pop().unwrap_or_default();
+
+    Ok(CliArgs {
+        pdf_dir,
+        workbook,
+        settings,
+        corrections,
+        pdftotext,
+        dry_run,
+    })
+}
+
+pub fn run() -> ExitCode {
+    dotenv().ok();
+
+    let args = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}\n", e);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match tauri::async_runtime::block_on(run_batch(args)) {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Errore: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run_batch(args: CliArgs) -> Result<usize, String> {
+    let settings = Settings::from_file(&args.settings)?;
+    let corrections = Settings::from_file(&args.corrections)?;
+    let api_key = |entry: &str| {
+        let var = if entry == llm::MISTRAL_KEY_ENTRY {
+            "MAGGUS_API_KEY"
+        } else {
+            "MAGGUS_LLM_API_KEY"
+        };
+        std::env::var(var)
+            .ok()
+            .filter(|k| !k.trim().is_empty())
+            .unwrap_or_else(|| crate::stored_api_key(entry))
+    };
+
+    let analyzer = Analyzer::new(
+        &settings,
+        &corrections,
+        &api_key,
+        Box::new(SystemPdftotextExtractor {
+            binary: args.pdftotext,
+        }),
+    );
+
+    let filenames = FilenameSchema::load(&settings);
+    let pdfs = list_pdfs(&args.pdf_dir)?;
+    if pdfs.is_empty() {
+        return Err(format!(
+            "Nessun documento (PDF o FatturaPA) trovato in {}",
+            args.pdf_dir.display()
+        ));
+    }
+
+    let total = pdfs.len();
+    let mut rows: Vec<ExportRow> = Vec::new();
+    let mut failed = 0;
+
+    for (i, path) in pdfs.iter().enumerate() {
+        let meta = filenames.parse(path);
+        let warning = if meta.warnings {
+            " (nome file incompleto)"
+        } else {
+            ""
+        };
+
+        match analyzer
+            .analyze(&path.to_string_lossy(), &meta.doc_type, false)
+            .await
+        {
+            Ok(result) => {
+                let exported = result.document.as_ref().and_then(|d| {
+                    d.exported_at
+                        .as_ref()
+                        .map(|at| (at.clone(), d.workbook.clone()))
+                });
+                let mismatches = result.mismatches.clone();
+                let doc_rows = rows_for_document(&meta, result);
+                println!(
+                    "[{}/{}] {}: {} prodotti{}",
+                    i + 1,
+                    total,
+                    meta.pdf_name,
+                    doc_rows.len(),
+                    warning
+                );
+                if let Some((at, workbook)) = exported {
+                    println!(
+                        "    attenzione: già esportato il {} in {}",
+                        at,
+                        workbook.as_deref().unwrap_or("-")
+                    );
+                }
+                for mismatch in mismatches {
+                    println!("    attenzione: {}", mismatch);
+                }
+                rows.extend(doc_rows);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("[{}/{}] {}: ERRORE {}", i + 1, total, meta.pdf_name, e);
+            }
+        }
+    }
+
+    println!(
+        "\nDocumenti: {} elaborati, {} falliti. Righe estratte: {}.",
+        total - failed,
+        failed,
+        rows.len()
+    );
+
+    if rows.is_empty() {
+        return Err("Nessun dato da esportare.".to_string());
+    }
+
+    let mapping = column_map::load_active_mapping(&settings);
+    let options = ExportOptions {
+        dry_run: args.dry_run,
+        reconciliation: Some(reconcile::load_settings(&settings)).filter(|r| r.enabled),
+        partial_invoices: excel::load_partial_invoices(&settings),
+        product_matcher: product_match::load(&settings, &corrections),
+        catalog: Catalog::load(&corrections),
+    };
+    let diff = if args.dry_run {
+        excel::export_rows(&args.workbook, rows, &mapping, &options, &|_, _| {})?
+    } else {
+        let exported = rows.clone();
+        let diff = BackupStore::from_settings(&settings)?.export(
+            &args.workbook,
+            rows,
+            &mapping,
+            &options,
+            &|_, _| {},
+        )?;
+        documents::record_export(&settings, &exported, &args.workbook);
+        diff
+    };
+
+    if let Some(report) = &diff.reconciliation {
+        for finding in &report.findings {
+            println!(
+                "Riconciliazione {} / {}: {}",
+                finding.nummer_auftrag, finding.produkt, finding.note
+            );
+        }
+    }
+
+    for warning in &diff.warnings {
+        println!("Attenzione: {}", warning);
+    }
+
+    for fuzzy in &diff.fuzzy_matches {
+        println!(
+            "Associato per somiglianza {} / {}: riga {} \"{}\" ({:.0}%)",
+            fuzzy.nummer_auftrag,
+            fuzzy.produkt,
+            fuzzy.candidate.row,
+            fuzzy.candidate.produkt,
+            fuzzy.candidate.score * 100.0
+        );
+    }
+
+    for ambiguous in &diff.ambiguous {
+        let candidates: Vec<String> = ambiguous
+            .candidates
+            .iter()
+            .map(|c| format!("riga {} \"{}\" ({:.0}%)", c.row, c.produkt, c.score * 100.0))
+            .collect();
+        println!(
+            "Corrispondenza incerta {} / {}: {}",
+            ambiguous.row.nummer_auftrag.as_deref().unwrap_or("-"),
+            ambiguous.row.produkt.as_deref().unwrap_or("-"),
+            candidates.join(", ")
+        );
+    }
+
+    if args.dry_run {
+        print_diff(&diff);
+    } else {
+        println!(
+            "Finito: {} aggiornati, {} nuovi inseriti, {} note di riconciliazione aggiornate.",
+            diff.updates.len(),
+            diff.insertions.len(),
+            diff.notes.len()
+        );
+    }
+
+    Ok(failed)
+}
+
+fn print_diff(diff: &ExportDiff) {
+    for update in &diff.updates {
+        println!(
+            "Riga {} ({} / {}):",
+            update.row, update.nummer_auftrag, update.produkt
+        );
+        for change in &update.changes {
+            println!(
+                "    {} [{}]: '{}' -> '{}'",
+                change.field, change.column, change.old_value, change.new_value
+            );
+        }
+    }
+
+    for note in &diff.notes {
+        println!(
+            "Nota riga {} ({} / {}) [{}]: '{}' -> '{}'",
+            note.row,
+            note.nummer_auftrag,
+            note.produkt,
+            note.change.column,
+            note.change.old_value,
+            note.change.new_value
+        );
+    }
+
+    for insertion in &diff.insertions {
+        println!(
+            "Nuova riga prima della riga {}: {} / {}",
+            insertion.before_row,
+            insertion.row.nummer_auftrag.as_deref().unwrap_or("-"),
+            insertion.row.produkt.as_deref().unwrap_or("-")
+        );
+    }
+
+    for row in &diff.unmatchable {
+        println!(
+            "Senza corrispondenza: {} / {}",
+            row.nummer_auftrag.as_deref().unwrap_or("-"),
+            row.produkt.as_deref().unwrap_or("-")
+        );
+    }
+
+    println!(
+        "Anteprima: {} da aggiornare, {} da inserire, {} note da aggiornare, {} già aggiornate, {} senza corrispondenza. Nessuna modifica salvata.",
+        diff.updates.len(),
+        diff.insertions.len(),
+        diff.notes.len(),
+        diff.unchanged,
+        diff.unmatchable.len()
+    );
+}
+
+fn list_pdfs(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut pdfs: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("Impossibile leggere {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.is_file()
+                && (p
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+                    || fattura_pa::has_document_extension(p))
+        })
+        .collect();
+    pdfs.sort();
+    Ok(pdfs)
+}
+
+fn rows_for_document(meta: &FileMetadata, result: AnalysisResult) -> Vec<ExportRow> {
+    let (nummer_auftrag, datum_auftrag, lieferant, kunde) = match &result.extraction {
+        Extraction::Auftrag(order) => (
+            order.nummer_auftrag.clone(),
+            order.datum_auftrag.clone(),
+            order.lieferant.clone(),
+            order.kunde.clone(),
+        ),
+        Extraction::Rechnung(invoice) => {
+            (None, None, invoice.lieferant.clone(), invoice.kunde.clone())
+        }
+        Extraction::Lieferschein(note) => (None, None, note.lieferant.clone(), note.kunde.clone()),
+        Extraction::Gutschrift(credit) => {
+            (None, None, credit.lieferant.clone(), credit.kunde.clone())
+        }
+    };
+    let base = ExportRow {
+        document_hash: result.document.as_ref().map(|d| d.hash.clone()),
+        datum_auftrag: meta.datum_auftrag.clone().or(datum_auftrag),
+        nummer_auftrag: meta.nummer_auftrag.clone().or(nummer_auftrag),
+        kunde: meta.kunde.clone().or(kunde),
+        lieferant: meta.lieferant.clone().or(lieferant),
+        datum_rechnung: meta.datum_rechnung.clone(),
+        ..Default::default()
+    };
+
+    match result.extraction {
+        Extraction::Auftrag(order) => order
+            .produkte
+            .into_iter()
+            .map(|p| ExportRow {
+                produkt: Some(p.produkt),
+                artikelnummer: p.artikelnummer,
+                einheit: p.einheit,
+                menge: p.menge,
+                waehrung: p.waehrung,
+                preis: p.preis,
+                ..base.clone()
+            })
+            .collect(),
+        Extraction::Rechnung(invoice) => invoice
+            .produkte
+            .into_iter()
+            .map(|p| ExportRow {
+                produkt: Some(p.produkt),
+                artikelnummer: p.artikelnummer,
+                einheit: p.einheit,
+                nummer_auftrag: p.nummer_auftrag.or_else(|| base.nummer_auftrag.clone()),
+                datum_rechnung: invoice
+                    .datum_rechnung
+                    .clone()
+                    .or_else(|| base.datum_rechnung.clone()),
+                gelieferte_menge: p.gelieferte_menge,
+                preis_rechnung: p.preis,
+                nummer_rechnung: invoice.nummer_rechnung.clone(),
+                ..base.clone()
+            })
+            .collect(),
+        Extraction::Lieferschein(note) => note
+            .produkte
+            .into_iter()
+            .map(|p| ExportRow {
+                produkt: Some(p.produkt),
+                artikelnummer: p.artikelnummer,
+                einheit: p.einheit,
+                nummer_auftrag: p.nummer_auftrag.or_else(|| base.nummer_auftrag.clone()),
+                nummer_ddt: note.nummer_ddt.clone(),
+                datum_ddt: note.datum_ddt.clone().or_else(|| meta.datum_ddt.clone()),
+                menge_ddt: p.gelieferte_menge,
+                ..base.clone()
+            })
+            .collect(),
+        Extraction::Gutschrift(credit) => credit
+            .produkte
+            .into_iter()
+            .map(|p| ExportRow {
+                produkt: Some(p.produkt),
+                artikelnummer: p.artikelnummer,
+                einheit: p.einheit,
+                nummer_auftrag: p.nummer_auftrag.or_else(|| base.nummer_auftrag.clone()),
+                nummer_gutschrift: credit.nummer_gutschrift.clone(),
+                menge_gutschrift: p.gelieferte_menge.map(f64::abs),
+                datum_rechnung: None,
+                ..base.clone()
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,322 @@
+use serde_json::json;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+use umya_spreadsheet::Worksheet;
+
+use crate::settings::Settings;
+
+const HEADER_SEARCH_LIMIT: u32 = 100;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ColumnRef {
+    Letter(String),
+    Header(String),
+}
+
+impl ColumnRef {
+    fn letter(letter: &str) -> Option<ColumnRef> {
+        Some(ColumnRef::Letter(letter.to_string()))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HeaderAnchor {
+    pub text: String,
+    pub column: Option<String>,
+}
+
+impl Default for HeaderAnchor {
+    fn default() -> Self {
+        HeaderAnchor {
+            text: "Casa Estera".to_string(),
+            column: Some("D".to_string()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ColumnMap {
+    pub datum_auftrag: Option<ColumnRef>,
+    pub nummer_auftrag: Option<ColumnRef>,
+    pub kunde: Option<ColumnRef>,
+    pub lieferant: Option<ColumnRef>,
+    pub produkt: Option<ColumnRef>,
+    pub artikelnummer: Option<ColumnRef>,
+    pub einheit: Option<ColumnRef>,
+    pub menge: Option<ColumnRef>,
+    pub waehrung: Option<ColumnRef>,
+    pub preis: Option<ColumnRef>,
+    pub datum_rechnung: Option<ColumnRef>,
+    pub nummer_rechnung: Option<ColumnRef>,
+    pub gelieferte_menge: Option<ColumnRef>,
+    pub preis_rechnung: Option<ColumnRef>,
+    pub nummer_ddt: Option<ColumnRef>,
+    pub datum_ddt: Option<ColumnRef>,
+    pub menge_ddt: Option<ColumnRef>,
+    pub nummer_gutschrift: Option<ColumnRef>,
+    pub formel: Option<ColumnRef>,
+    pub anmerkungen: Option<ColumnRef>,
+}
+
+impl Default for ColumnMap {
+    fn default() -> Self {
+        ColumnMap {
+            datum_auftrag: ColumnRef::letter("A"),
+            nummer_auftrag: ColumnRef::letter("B"),
+            kunde: ColumnRef::letter("C"),
+            lieferant: ColumnRef::letter("D"),
+            produkt: ColumnRef::letter("E"),
+            artikelnummer: None,
+            einheit: None,
+            menge: ColumnRef::letter("F"),
+            waehrung: ColumnRef::letter("G"),
+            preis: ColumnRef::letter("H"),
+            datum_rechnung: ColumnRef::letter("J"),
+            nummer_rechnung: ColumnRef::letter("K"),
+            gelieferte_menge: ColumnRef::letter("L"),
+            preis_rechnung: None,
+            nummer_ddt: None,
+            datum_ddt: None,
+            menge_ddt: None,
+            nummer_gutschrift: None,
+            formel: ColumnRef::letter("M"),
+            anmerkungen: ColumnRef::letter("R"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ColumnMapping {
+    pub name: String,
+    pub header_anchor: HeaderAnchor,
+    pub columns: ColumnMap,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping {
+            name: "Standard".to_string(),
+            header_anchor: HeaderAnchor::default(),
+            columns: ColumnMap::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SheetLayout {
+    pub header_row: u32,
+    pub datum_auftrag: Option<u32>,
+    pub nummer_auftrag: u32,
+    pub kunde: Option<u32>,
+    pub lieferant: Option<u32>,
+    pub produkt: u32,
+    pub artikelnummer: Option<u32>,
+    pub einheit: Option<u32>,
+    pub menge: Option<u32>,
+    pub waehrung: Option<u32>,
+    pub preis: Option<u32>,
+    pub datum_rechnung: Option<u32>,
+    pub nummer_rechnung: Option<u32>,
+    pub gelieferte_menge: Option<u32>,
+    pub preis_rechnung: Option<u32>,
+    pub nummer_ddt: Option<u32>,
+    pub datum_ddt: Option<u32>,
+    pub menge_ddt: Option<u32>,
+    pub nummer_gutschrift: Option<u32>,
+    pub formel: Option<u32>,
+    pub anmerkungen: Option<u32>,
+}
+
+impl SheetLayout {
+    pub fn last_column(&self) -> u32 {
+        [
+            self.datum_auftrag,
+            Some(self.nummer_auftrag),
+            self.kunde,
+            self.lieferant,
+            Some(self.produkt),
+            self.artikelnummer,
+            self.einheit,
+            self.menge,
+            self.waehrung,
+            self.preis,
+            self.datum_rechnung,
+            self.nummer_rechnung,
+            self.gelieferte_menge,
+            self.preis_rechnung,
+            self.nummer_ddt,
+            self.datum_ddt,
+            self.menge_ddt,
+            self.nummer_gutschrift,
+            self.formel,
+            self.anmerkungen,
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or(1)
+    }
+
+    pub fn is_date_column(&self, col: u32) -> bool {
+        self.datum_auftrag == Some(col)
+            || self.datum_rechnung == Some(col)
+            || self.datum_ddt == Some(col)
+    }
+}
+
+pub fn column_index(letter: &str) -> Option<u32> {
+    let letter = letter.trim();
+    if letter.is_empty() || letter.len() > 3 || !letter.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some(
+        letter
+            .to_ascii_uppercase()
+            .bytes()
+            .fold(0, |acc, b| acc * 26 + (b - b'A' + 1) as u32),
+    )
+}
+
+pub fn column_letter(mut index: u32) -> String {
+    let mut letters = Vec::new();
+    while index > 0 {
+        let rem = (index - 1) % 26;
+        letters.push((b'A' + rem as u8) as char);
+        index = (index - 1) / 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn find_header_row(sheet: &Worksheet, anchor: &HeaderAnchor) -> Result<u32, String> {
+    let anchor_col = match &anchor.column {
+        Some(letter) if !letter.trim().is_empty() => Some(
+            column_index(letter)
+                .ok_or_else(|| format!("Colonna di ancoraggio non valida: {}", letter))?,
+        ),
+        _ => None,
+    };
+
+    let search_limit = sheet.get_highest_row().min(HEADER_SEARCH_LIMIT);
+    let last_col = sheet.get_highest_column();
+    let text = anchor.text.trim();
+
+    for r in 1..=search_limit {
+        let found = match anchor_col {
+            Some(col) => sheet.get_value((col, r)).trim().eq_ignore_ascii_case(text),
+            None => (1..=last_col)
+                .any(|col| sheet.get_value((col, r)).trim().eq_ignore_ascii_case(text)),
+        };
+        if found {
+            return Ok(r);
+        }
+    }
+
+    Ok(1)
+}
+
+fn resolve(
+    sheet: &Worksheet,
+    header_row: u32,
+    column: &Option<ColumnRef>,
+) -> Result<Option<u32>, String> {
+    match column {
+        None => Ok(None),
+        Some(ColumnRef::Letter(letter)) => column_index(letter)
+            .map(Some)
+            .ok_or_else(|| format!("Lettera di colonna non valida: {}", letter)),
+        Some(ColumnRef::Header(name)) => (1..=sheet.get_highest_column())
+            .find(|&col| {
+                sheet
+                    .get_value((col, header_row))
+                    .trim()
+                    .eq_ignore_ascii_case(name.trim())
+            })
+            .map(Some)
+            .ok_or_else(|| format!("Colonna '{}' non trovata nell'intestazione.", name)),
+    }
+}
+
+impl ColumnMapping {
+    pub fn resolve(&self, sheet: &Worksheet) -> Result<SheetLayout, String> {
+        let header_row = find_header_row(sheet, &self.header_anchor)?;
+        let c = &self.columns;
+        let col = |column: &Option<ColumnRef>| resolve(sheet, header_row, column);
+        let required = |column: &Option<ColumnRef>, field: &str| {
+            col(column)?.ok_or_else(|| {
+                format!(
+                    "La mappatura '{}' non assegna una colonna a {}.",
+                    self.name, field
+                )
+            })
+        };
+
+        Ok(SheetLayout {
+            header_row,
+            datum_auftrag: col(&c.datum_auftrag)?,
+            nummer_auftrag: required(&c.nummer_auftrag, "nummerAuftrag")?,
+            kunde: col(&c.kunde)?,
+            lieferant: col(&c.lieferant)?,
+            produkt: required(&c.produkt, "produkt")?,
+            artikelnummer: col(&c.artikelnummer)?,
+            einheit: col(&c.einheit)?,
+            menge: col(&c.menge)?,
+            waehrung: col(&c.waehrung)?,
+            preis: col(&c.preis)?,
+            datum_rechnung: col(&c.datum_rechnung)?,
+            nummer_rechnung: col(&c.nummer_rechnung)?,
+            gelieferte_menge: col(&c.gelieferte_menge)?,
+            preis_rechnung: col(&c.preis_rechnung)?,
+            nummer_ddt: col(&c.nummer_ddt)?,
+            datum_ddt: col(&c.datum_ddt)?,
+            menge_ddt: col(&c.menge_ddt)?,
+            nummer_gutschrift: col(&c.nummer_gutschrift)?,
+            formel: col(&c.formel)?,
+            anmerkungen: col(&c.anmerkungen)?,
+        })
+    }
+}
+
+pub fn load_mappings(settings: &Settings) -> (Vec<ColumnMapping>, String) {
+    let mut mappings: Vec<ColumnMapping> = settings.get_as("excelMappings").unwrap_or_default();
+
+    if mappings.is_empty() {
+        mappings.push(ColumnMapping::default());
+    }
+
+    let active = settings
+        .get("activeExcelMapping")
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .filter(|name| mappings.iter().any(|m| &m.name == name))
+        .unwrap_or_else(|| mappings[0].name.clone());
+
+    (mappings, active)
+}
+
+pub fn load_active_mapping(settings: &Settings) -> ColumnMapping {
+    let (mappings, active) = load_mappings(settings);
+    mappings
+        .into_iter()
+        .find(|m| m.name == active)
+        .unwrap_or_default()
+}
+
+pub fn save_mappings(
+    app: &AppHandle,
+    mappings: &[ColumnMapping],
+    active: &str,
+) -> Result<(), String> {
+    let store = app
+        .store("settings.json")
+        .map_err(|e| format!("Store errore: {}", e))?;
+
+    store.set("excelMappings", json!(mappings));
+    store.set("activeExcelMapping", json!(active));
+    store
+        .save()
+        .map_err(|e| format!("Errore di memoria: {}", e))
+}
@@ -0,0 +1,418 @@
+use regex::Regex;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use crate::extraction::Extraction;
+use crate::filename::FileMetadata;
+use crate::settings::Settings;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CorrectionField {
+    #[default]
+    Produkt,
+    Einheit,
+    Waehrung,
+    Lieferant,
+    Kunde,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    Normalized,
+    Regex,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CorrectionRule {
+    pub name: String,
+    pub field: CorrectionField,
+    pub mode: MatchMode,
+    pub pattern: String,
+    pub replacement: String,
+    pub lieferant: Option<String>,
+    pub kunde: Option<String>,
+    pub priority: i32,
+    pub enabled: bool,
+}
+
+impl Default for CorrectionRule {
+    fn default() -> Self {
+        CorrectionRule {
+            name: String::new(),
+            field: CorrectionField::Produkt,
+            mode: MatchMode::Exact,
+            pattern: String::new(),
+            replacement: String::new(),
+            lieferant: None,
+            kunde: None,
+            priority: 0,
+            enabled: true,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppliedCorrection {
+    pub rule: String,
+    pub field: CorrectionField,
+    pub before: String,
+    pub after: String,
+    pub explanation: String,
+}
+
+struct CompiledRule {
+    rule: CorrectionRule,
+    regex: Option<Regex>,
+}
+
+#[derive(Default)]
+pub struct Corrections {
+    rules: Vec<CompiledRule>,
+    errors: Vec<String>,
+}
+
+fn normalize(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn in_scope(scope: &Option<String>, value: Option<&str>) -> bool {
+    let Some(scope) = scope.as_deref().map(normalize).filter(|s| !s.is_empty()) else {
+        return true;
+    };
+    value.is_some_and(|v| normalize(v).contains(&scope))
+}
+
+fn learned_rules(learned: HashMap<String, String>) -> Vec<CorrectionRule> {
+    let mut learned: Vec<(String, String)> = learned.into_iter().collect();
+    learned.sort();
+    learned
+        .into_iter()
+        .map(|(wrong, correct)| CorrectionRule {
+            name: format!("Correzione appresa «{}»", wrong),
+            pattern: wrong,
+            replacement: correct,
+            ..Default::default()
+        })
+        .collect()
+}
+
+impl CompiledRule {
+    fn new(rule: CorrectionRule) -> Result<CompiledRule, String> {
+        if rule.pattern.trim().is_empty() {
+            return Err(format!("Regola «{}»: criterio vuoto", rule.name));
+        }
+        let regex = match rule.mode {
+            MatchMode::Regex => Some(
+                Regex::new(&rule.pattern)
+                    .map_err(|e| format!("Regola «{}» non valida: {}", rule.name, e))?,
+            ),
+            _ => None,
+        };
+        Ok(CompiledRule { rule, regex })
+    }
+
+    fn replace(&self, value: &str) -> Option<String> {
+        let rule = &self.rule;
+        match (&self.regex, rule.mode) {
+            (Some(regex), _) => regex.is_match(value).then(|| {
+                regex
+                    .replace_all(value, rule.replacement.as_str())
+                    .into_owned()
+            }),
+            (None, MatchMode::Normalized) => {
+                (normalize(value) == normalize(&rule.pattern)).then(|| rule.replacement.clone())
+            }
+            _ => (value.trim() == rule.pattern.trim()).then(|| rule.replacement.clone()),
+        }
+    }
+}
+
+impl Corrections {
+    pub fn new(rules: Vec<CorrectionRule>) -> Result<Corrections, String> {
+        let corrections = Corrections::compile(rules);
+        match corrections.errors.first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(corrections),
+        }
+    }
+
+    // Compiles each rule on its own so a broken rule only disables itself.
+    pub fn compile(rules: Vec<CorrectionRule>) -> Corrections {
+        let mut corrections = Corrections::default();
+        for rule in rules {
+            match CompiledRule::new(rule) {
+                Ok(rule) if rule.rule.enabled => corrections.rules.push(rule),
+                Ok(_) => {}
+                Err(e) => corrections.errors.push(e),
+            }
+        }
+        corrections.rules.sort_by_key(|r| Reverse(r.rule.priority));
+        corrections
+    }
+
+    pub fn load(corrections: &Settings) -> Corrections {
+        let mut rules = load_rules(corrections);
+        rules.extend(learned_rules(
+            corrections
+                .get_as("product_corrections")
+                .unwrap_or_default(),
+        ));
+        let corrections = Corrections::compile(rules);
+        for e in &corrections.errors {
+            println!("Regola di correzione ignorata: {}", e);
+        }
+        corrections
+    }
+
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    fn correct(
+        &self,
+        field: CorrectionField,
+        value: &mut String,
+        lieferant: Option<&str>,
+        kunde: Option<&str>,
+    ) -> Option<AppliedCorrection> {
+        let (rule, after) = self
+            .rules
+            .iter()
+            .filter(|r| r.rule.field == field)
+            .filter(|r| in_scope(&r.rule.lieferant, lieferant) && in_scope(&r.rule.kunde, kunde))
+            .find_map(|r| {
+                r.replace(value)
+                    .filter(|after| after != value)
+                    .map(|after| (&r.rule, after))
+            })?;
+
+        let before = std::mem::replace(value, after.clone());
+        let scope = [("fornitore", &rule.lieferant), ("cliente", &rule.kunde)]
+            .iter()
+            .filter_map(|(label, scope)| scope.as_ref().map(|s| format!("{} «{}»", label, s)))
+            .collect::<Vec<_>>();
+        let explanation = format!(
+            "«{}» → «{}»: regola «{}» (priorità {}{})",
+            before,
+            after,
+            rule.name,
+            rule.priority,
+            if scope.is_empty() {
+                String::new()
+            } else {
+                format!(", {}", scope.join(", "))
+            }
+        );
+        Some(AppliedCorrection {
+            rule: rule.name.clone(),
+            field,
+            before,
+            after,
+            explanation,
+        })
+    }
+
+    pub fn apply(
+        &self,
+        extraction: &mut Extraction,
+        meta: &FileMetadata,
+    ) -> Vec<AppliedCorrection> {
+        let mut applied = Vec::new();
+
+        let (lieferant, kunde) = extraction.parties_mut();
+        if let Some(value) = lieferant {
+            applied.extend(self.correct(CorrectionField::Lieferant, value, None, None));
+        }
+        if let Some(value) = kunde {
+            applied.extend(self.correct(CorrectionField::Kunde, value, None, None));
+        }
+
+        let (lieferant, kunde) = extraction.parties_mut();
+        let lieferant = lieferant.clone().or_else(|| meta.lieferant.clone());
+        let kunde = kunde.clone().or_else(|| meta.kunde.clone());
+        let (lieferant, kunde) = (lieferant.as_deref(), kunde.as_deref());
+
+        for name in extraction.product_names_mut() {
+            applied.extend(self.correct(CorrectionField::Produkt, name, lieferant, kunde));
+        }
+        for unit in extraction.units_mut() {
+            applied.extend(self.correct(CorrectionField::Einheit, unit, lieferant, kunde));
+        }
+        for currency in extraction.currencies_mut() {
+            applied.extend(self.correct(CorrectionField::Waehrung, currency, lieferant, kunde));
+        }
+
+        applied
+    }
+}
+
+pub fn load_rules(corrections: &Settings) -> Vec<CorrectionRule> {
+    corrections.get_as("correction_rules").unwrap_or_default()
+}
+
+pub fn save_rules(app: &AppHandle, rules: &[CorrectionRule]) -> Result<(), String> {
+    Corrections::new(rules.to_vec())?;
+
+    let store = app
+        .store("corrections.json")
+        .map_err(|e| format!("Store errore: {}", e))?;
+
+    store.set("correction_rules", json!(rules));
+    store
+        .save()
+        .map_err(|e| format!("Errore di memoria: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, mode: MatchMode, pattern: &str, replacement: &str) -> CorrectionRule {
+        CorrectionRule {
+            name: name.to_string(),
+            mode,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn correct(corrections: &Corrections, value: &str, lieferant: Option<&str>) -> String {
+        let mut value = value.to_string();
+        corrections.correct(CorrectionField::Produkt, &mut value, lieferant, None);
+        value
+    }
+
+    #[test]
+    fn higher_priority_rules_win() {
+        let low = rule("Generica", MatchMode::Normalized, "semi cardo", "CARDO");
+        let high = CorrectionRule {
+            priority: 10,
+            ..rule(
+                "Specifica",
+                MatchMode::Normalized,
+                "semi cardo",
+                "CARDO MARIANO SEMI",
+            )
+        };
+        let corrections = Corrections::new(vec![low, high]).unwrap();
+
+        let mut value = "Semi  Cardo".to_string();
+        let applied = corrections
+            .correct(CorrectionField::Produkt, &mut value, None, None)
+            .unwrap();
+        assert_eq!(value, "CARDO MARIANO SEMI");
+        assert_eq!(applied.rule, "Specifica");
+        assert_eq!(applied.before, "Semi  Cardo");
+        assert!(applied.explanation.contains("priorità 10"));
+    }
+
+    #[test]
+    fn scoped_rules_only_apply_to_their_supplier_and_customer() {
+        let scoped = CorrectionRule {
+            lieferant: Some("Müller".to_string()),
+            ..rule("Müller", MatchMode::Exact, "CARDO", "CARDO MARIANO SEMI")
+        };
+        let corrections = Corrections::new(vec![scoped]).unwrap();
+        assert_eq!(
+            correct(&corrections, "CARDO", Some("Müller GmbH")),
+            "CARDO MARIANO SEMI"
+        );
+        assert_eq!(correct(&corrections, "CARDO", Some("Rossi")), "CARDO");
+        assert_eq!(correct(&corrections, "CARDO", None), "CARDO");
+
+        let for_customer = CorrectionRule {
+            field: CorrectionField::Waehrung,
+            kunde: Some("bianchi".to_string()),
+            ..rule("Valuta", MatchMode::Exact, "€", "EUR")
+        };
+        let corrections = Corrections::new(vec![for_customer]).unwrap();
+        let mut currency = "€".to_string();
+        corrections.correct(
+            CorrectionField::Waehrung,
+            &mut currency,
+            None,
+            Some("Rossi"),
+        );
+        assert_eq!(currency, "€");
+        corrections.correct(
+            CorrectionField::Waehrung,
+            &mut currency,
+            None,
+            Some("Anna Bianchi"),
+        );
+        assert_eq!(currency, "EUR");
+    }
+
+    #[test]
+    fn unit_rules_apply_to_the_extracted_units_only() {
+        let unit = CorrectionRule {
+            field: CorrectionField::Einheit,
+            lieferant: Some("Müller".to_string()),
+            ..rule("Chilogrammi", MatchMode::Normalized, "kgs", "kg")
+        };
+        let corrections = Corrections::new(vec![unit]).unwrap();
+        let mut extraction: Extraction = serde_json::from_value(json!({
+            "docType": "rechnung",
+            "lieferant": "Müller GmbH",
+            "produkte": [
+                { "produkt": "KGS", "einheit": "KGS" },
+                { "produkt": "Semi di cardo", "einheit": null }
+            ]
+        }))
+        .unwrap();
+
+        let applied = corrections.apply(&mut extraction, &FileMetadata::default());
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].field, CorrectionField::Einheit);
+        let Extraction::Rechnung(invoice) = extraction else {
+            panic!("expected an invoice");
+        };
+        assert_eq!(invoice.produkte[0].einheit.as_deref(), Some("kg"));
+        assert_eq!(invoice.produkte[0].produkt, "KGS");
+        assert_eq!(invoice.produkte[1].einheit, None);
+    }
+
+    #[test]
+    fn regex_rules_replace_with_capture_groups() {
+        let corrections = Corrections::new(vec![rule(
+            "Formato",
+            MatchMode::Regex,
+            r"(?i)^vite\s+m(\d+)$",
+            "VITE M$1 ZINCATA",
+        )])
+        .unwrap();
+        assert_eq!(correct(&corrections, "vite m8", None), "VITE M8 ZINCATA");
+        assert_eq!(correct(&corrections, "vite m8 inox", None), "vite m8 inox");
+    }
+
+    #[test]
+    fn a_broken_rule_only_disables_itself() {
+        let broken = rule("Rotta", MatchMode::Regex, "(semi", "X");
+        let disabled = CorrectionRule {
+            enabled: false,
+            ..rule("Spenta", MatchMode::Exact, "CARDO", "SPENTA")
+        };
+        let valid = rule("Cardo", MatchMode::Exact, "CARDO", "CARDO MARIANO SEMI");
+
+        let corrections = Corrections::compile(vec![broken.clone(), disabled, valid]);
+        assert_eq!(corrections.errors().len(), 1);
+        assert!(corrections.errors()[0].contains("«Rotta»"));
+        assert_eq!(correct(&corrections, "CARDO", None), "CARDO MARIANO SEMI");
+
+        assert!(Corrections::new(vec![broken]).is_err());
+    }
+}
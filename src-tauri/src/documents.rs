@@ -0,0 +1,294 @@
+use chrono::Local;
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::excel::ExportRow;
+use crate::extraction::{AnalysisResult, SCHEMA_VERSION};
+use crate::settings::{self, Settings};
+use crate::usage::{StoredUsage, UsageEntry};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    hash TEXT PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    path TEXT NOT NULL,
+    doc_type TEXT NOT NULL,
+    extracted_text TEXT,
+    ocr_text TEXT,
+    result TEXT,
+    corrected_rows TEXT,
+    exported_at TEXT,
+    workbook TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    document_hash TEXT NOT NULL,
+    batch_id TEXT,
+    supplier TEXT,
+    entry TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS usage_created_at ON usage (created_at);";
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentStatus {
+    pub hash: String,
+    pub cached: bool,
+    pub exported_at: Option<String>,
+    pub workbook: Option<String>,
+    // Rows as last exported, user edits included; shown instead of the extraction on a cache hit.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corrected_rows: Vec<ExportRow>,
+}
+
+#[derive(Default)]
+pub struct SourceText {
+    pub text: Option<String>,
+    pub ocr_text: Option<String>,
+}
+
+pub struct DocumentDb {
+    conn: Connection,
+}
+
+pub fn hash_file(path: &Path) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Impossibile leggere il file: {}", e))?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+pub fn db_path(settings: &Settings) -> Option<PathBuf> {
+    settings
+        .get("documentDbPath")
+        .and_then(|v| v.as_str())
+        .filter(|p| !p.trim().is_empty())
+        .map(PathBuf::from)
+        .or_else(|| settings::default_store_path("documents.sqlite3"))
+}
+
+pub fn record_export(settings: &Settings, rows: &[ExportRow], workbook: &Path) {
+    let Some(path) = db_path(settings) else {
+        return;
+    };
+    if let Err(e) = DocumentDb::open(&path).and_then(|db| db.mark_exported(rows, workbook)) {
+        println!("Archivio documenti: {}", e);
+    }
+}
+
+impl DocumentDb {
+    pub fn open(path: &Path) -> Result<DocumentDb, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Impossibile creare la cartella dell'archivio: {}", e))?;
+        }
+
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Impossibile aprire l'archivio documenti: {}", e))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| format!("Archivio documenti non valido: {}", e))?;
+
+        Ok(DocumentDb { conn })
+    }
+
+    pub fn cached_result(
+        &self,
+        hash: &str,
+        doc_type: &str,
+    ) -> Result<Option<AnalysisResult>, String> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT result, exported_at, workbook, corrected_rows FROM documents
+                 WHERE hash = ?1 AND doc_type = ?2 AND result IS NOT NULL",
+                params![hash, doc_type],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Lettura archivio documenti non riuscita: {}", e))?;
+
+        let Some((result, exported_at, workbook, corrected_rows)) = row else {
+            return Ok(None);
+        };
+        let Ok(mut result) = serde_json::from_str::<AnalysisResult>(&result) else {
+            return Ok(None);
+        };
+        if result.schema_version != SCHEMA_VERSION {
+            return Ok(None);
+        }
+
+        result.document = Some(DocumentStatus {
+            hash: hash.to_string(),
+            cached: true,
+            exported_at,
+            workbook,
+            corrected_rows: corrected_rows
+                .and_then(|rows| {
+                    serde_json::from_str(&rows)
+                        .map_err(|e| println!("Righe corrette non valide: {}", e))
+                        .ok()
+                })
+                .unwrap_or_default(),
+        });
+        Ok(Some(result))
+    }
+
+    pub fn save_analysis(
+        &self,
+        hash: &str,
+        path: &Path,
+        doc_type: &str,
+        source: &SourceText,
+        result: &AnalysisResult,
+    ) -> Result<DocumentStatus, String> {
+        let now = Local::now().to_rfc3339();
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let json = serde_json::to_string(result).map_err(|e| e.to_string())?;
+
+        self.conn
+            .execute(
+                "INSERT INTO documents
+                    (hash, file_name, path, doc_type, extracted_text, ocr_text, result, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)
+                 ON CONFLICT(hash) DO UPDATE SET
+                    file_name = excluded.file_name,
+                    path = excluded.path,
+                    doc_type = excluded.doc_type,
+                    extracted_text = excluded.extracted_text,
+                    ocr_text = excluded.ocr_text,
+                    result = excluded.result,
+                    corrected_rows = NULL,
+                    updated_at = excluded.updated_at",
+                params![
+                    hash,
+                    file_name,
+                    path.to_string_lossy().to_string(),
+                    doc_type,
+                    source.text,
+                    source.ocr_text,
+                    json,
+                    now
+                ],
+            )
+            .map_err(|e| format!("Salvataggio nell'archivio documenti non riuscito: {}", e))?;
+
+        self.conn
+            .query_row(
+                "SELECT exported_at, workbook FROM documents WHERE hash = ?1",
+                params![hash],
+                |row| {
+                    Ok(DocumentStatus {
+                        hash: hash.to_string(),
+                        cached: false,
+                        exported_at: row.get(0)?,
+                        workbook: row.get(1)?,
+                        corrected_rows: Vec::new(),
+                    })
+                },
+            )
+            .map_err(|e| format!("Lettura archivio documenti non riuscita: {}", e))
+    }
+
+    pub fn record_usage(
+        &self,
+        hash: &str,
+        batch_id: Option<&str>,
+        supplier: Option<&str>,
+        entries: &[UsageEntry],
+    ) -> Result<(), String> {
+        let now = Local::now().to_rfc3339();
+        for entry in entries {
+            let json = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+            self.conn
+                .execute(
+                    "INSERT INTO usage (document_hash, batch_id, supplier, entry, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![hash, batch_id, supplier, json, now],
+                )
+                .map_err(|e| format!("Salvataggio consumi non riuscito: {}", e))?;
+        }
+        Ok(())
+    }
+
+    pub fn usage(&self, from: Option<&str>, to: Option<&str>) -> Result<Vec<StoredUsage>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT substr(created_at, 1, 10), document_hash, batch_id, supplier, entry
+                 FROM usage
+                 WHERE (?1 IS NULL OR substr(created_at, 1, 10) >= ?1)
+                   AND (?2 IS NULL OR substr(created_at, 1, 10) <= ?2)
+                 ORDER BY created_at",
+            )
+            .map_err(|e| format!("Lettura consumi non riuscita: {}", e))?;
+
+        let rows = stmt
+            .query_map(params![from, to], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .map_err(|e| format!("Lettura consumi non riuscita: {}", e))?;
+
+        let mut usage = Vec::new();
+        for row in rows {
+            let (day, document_hash, batch_id, supplier, entry) =
+                row.map_err(|e| format!("Lettura consumi non riuscita: {}", e))?;
+            match serde_json::from_str(&entry) {
+                Ok(entry) => usage.push(StoredUsage {
+                    day,
+                    document_hash,
+                    batch_id,
+                    supplier,
+                    entry,
+                }),
+                Err(e) => println!("Voce di consumo non valida: {}", e),
+            }
+        }
+        Ok(usage)
+    }
+
+    fn mark_exported(&self, rows: &[ExportRow], workbook: &Path) -> Result<usize, String> {
+        let mut by_document: HashMap<&str, Vec<&ExportRow>> = HashMap::new();
+        for row in rows {
+            if let Some(hash) = row.document_hash.as_deref().filter(|h| !h.is_empty()) {
+                by_document.entry(hash).or_default().push(row);
+            }
+        }
+
+        let now = Local::now().to_rfc3339();
+        let mut marked = 0;
+        for (hash, rows) in by_document {
+            let corrected = serde_json::to_string(&rows).map_err(|e| e.to_string())?;
+            marked += self
+                .conn
+                .execute(
+                    "UPDATE documents
+                     SET corrected_rows = ?2, exported_at = ?3, workbook = ?4, updated_at = ?3
+                     WHERE hash = ?1",
+                    params![hash, corrected, now, workbook.to_string_lossy().to_string()],
+                )
+                .map_err(|e| format!("Aggiornamento archivio documenti non riuscito: {}", e))?;
+        }
+
+        Ok(marked)
+    }
+}
@@ -0,0 +1,1498 @@
+use chrono::NaiveDate;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
+use umya_spreadsheet::{Spreadsheet, Worksheet};
+
+use crate::catalog::Catalog;
+use crate::column_map::{column_letter, ColumnMapping, SheetLayout};
+use crate::product_match::{MatchCandidate, ProductMatch, ProductMatcher};
+use crate::reconcile::{
+    self, line_key, InvoiceLine, ReconcileLine, ReconcileSettings, ReconciliationReport,
+};
+use crate::settings::Settings;
+use crate::workbook_lock;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRow {
+    pub datum_auftrag: Option<String>,
+    pub nummer_auftrag: Option<String>,
+    pub kunde: Option<String>,
+    pub lieferant: Option<String>,
+    pub produkt: Option<String>,
+    pub artikelnummer: Option<String>,
+    pub einheit: Option<String>,
+    pub menge: Option<f64>,
+    pub waehrung: Option<String>,
+    pub preis: Option<f64>,
+    pub datum_rechnung: Option<String>,
+    pub nummer_rechnung: Option<String>,
+    pub gelieferte_menge: Option<f64>,
+    pub preis_rechnung: Option<f64>,
+    pub nummer_ddt: Option<String>,
+    pub datum_ddt: Option<String>,
+    pub menge_ddt: Option<f64>,
+    pub nummer_gutschrift: Option<String>,
+    pub menge_gutschrift: Option<f64>,
+    pub anmerkungen: Option<String>,
+    pub document_hash: Option<String>,
+}
+struct SheetRow {
+    row_idx: u32,
+    supplier: String,
+    date: NaiveDate,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CellChange {
+    pub field: String,
+    pub column: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RowUpdate {
+    pub row: u32,
+    pub nummer_auftrag: String,
+    pub produkt: String,
+    pub changes: Vec<CellChange>,
+    #[serde(skip)]
+    data: ExportRow,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteUpdate {
+    pub row: u32,
+    pub nummer_auftrag: String,
+    pub produkt: String,
+    pub change: CellChange,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RowInsertion {
+    pub before_row: u32,
+    pub row: ExportRow,
+}
+
+#[derive(serde::Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportDiff {
+    pub header_row: u32,
+    pub updates: Vec<RowUpdate>,
+    pub insertions: Vec<RowInsertion>,
+    pub notes: Vec<NoteUpdate>,
+    pub unmatchable: Vec<ExportRow>,
+    pub ambiguous: Vec<AmbiguousMatch>,
+    pub fuzzy_matches: Vec<FuzzyMatch>,
+    pub reconciliation: Option<ReconciliationReport>,
+    pub unchanged: usize,
+    pub warnings: Vec<String>,
+}
+
+impl ExportDiff {
+    // Rows whose cells already hold the exported values are not written and not counted as updated.
+    fn push_update(&mut self, update: RowUpdate) {
+        if update.changes.is_empty() {
+            self.unchanged += 1;
+        } else {
+            self.updates.push(update);
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AmbiguousMatch {
+    pub row: ExportRow,
+    pub candidates: Vec<MatchCandidate>,
+}
+
+#[derive(serde::Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FuzzyMatch {
+    pub nummer_auftrag: String,
+    pub produkt: String,
+    pub candidate: MatchCandidate,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PartialInvoices {
+    #[default]
+    Accumulate,
+    SubRows,
+}
+
+#[derive(Clone, Default)]
+pub struct ExportOptions {
+    pub dry_run: bool,
+    pub reconciliation: Option<ReconcileSettings>,
+    pub partial_invoices: PartialInvoices,
+    pub product_matcher: Option<ProductMatcher>,
+    pub catalog: Catalog,
+}
+
+pub fn load_partial_invoices(settings: &Settings) -> PartialInvoices {
+    settings.get_as("partialInvoices").unwrap_or_default()
+}
+
+pub fn parse_date(date_str: &str) -> Option<NaiveDate> {
+    if let Ok(d) = NaiveDate::parse_from_str(date_str, "%d.%m.%Y") {
+        return Some(d);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+        return Some(d);
+    }
+
+    if let Ok(days) = date_str.parse::<i64>() {
+        let base = NaiveDate::from_ymd_opt(1899, 12, 30)?;
+        return base.checked_add_signed(chrono::Duration::days(days));
+    }
+    None
+}
+
+fn adjust_formula(formula: &str, old_row: u32, new_row: u32) -> String {
+    let pattern = format!(r"([A-Z]){}\b", old_row);
+    let re = Regex::new(&pattern).unwrap();
+    re.replace_all(formula, format!("${{1}}{}", new_row))
+        .to_string()
+}
+
+fn set_text(sheet: &mut Worksheet, col: Option<u32>, row: u32, value: &Option<String>) {
+    if let (Some(col), Some(v)) = (col, value) {
+        sheet.get_cell_mut((col, row)).set_value(v);
+    }
+}
+
+fn set_number(sheet: &mut Worksheet, col: Option<u32>, row: u32, value: Option<f64>) {
+    if let (Some(col), Some(v)) = (col, value) {
+        sheet.get_cell_mut((col, row)).set_value_number(v);
+    }
+}
+
+fn text_change(
+    sheet: &Worksheet,
+    field: &str,
+    col: Option<u32>,
+    row: u32,
+    value: &Option<String>,
+) -> Option<CellChange> {
+    let (col, new_value) = (col?, value.as_ref()?);
+    let old_value = sheet.get_value((col, row));
+    if old_value == *new_value {
+        return None;
+    }
+    Some(CellChange {
+        field: field.to_string(),
+        column: column_letter(col),
+        old_value,
+        new_value: new_value.clone(),
+    })
+}
+
+fn number_change(
+    sheet: &Worksheet,
+    field: &str,
+    col: Option<u32>,
+    row: u32,
+    value: Option<f64>,
+) -> Option<CellChange> {
+    let (col, new_value) = (col?, value?);
+    let old_value = sheet.get_value((col, row));
+    if old_value.trim().parse::<f64>().ok() == Some(new_value) {
+        return None;
+    }
+    Some(CellChange {
+        field: field.to_string(),
+        column: column_letter(col),
+        old_value,
+        new_value: new_value.to_string(),
+    })
+}
+
+fn sheet_number(sheet: &Worksheet, col: Option<u32>, row: u32) -> Option<f64> {
+    col.and_then(|c| {
+        sheet
+            .get_value((c, row))
+            .trim()
+            .replace(',', ".")
+            .parse()
+            .ok()
+    })
+}
+
+fn credit_token(nummer: &str) -> String {
+    format!("NC {}", nummer.trim()).trim_end().to_string()
+}
+
+fn credit_column(layout: &SheetLayout, row: &ExportRow) -> Option<u32> {
+    row.menge_gutschrift?;
+    layout.nummer_gutschrift.or(layout.anmerkungen)
+}
+
+fn credit_recorded(sheet: &Worksheet, layout: &SheetLayout, row_idx: u32, nummer: &str) -> bool {
+    let token = credit_token(nummer);
+    layout
+        .nummer_gutschrift
+        .or(layout.anmerkungen)
+        .is_some_and(|col| {
+            sheet
+                .get_value((col, row_idx))
+                .split([',', ';'])
+                .any(|t| t.trim().eq_ignore_ascii_case(&token))
+        })
+}
+
+fn resolve_credit(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    row_idx: Option<u32>,
+    row: &mut ExportRow,
+) {
+    let Some(credited) = row.menge_gutschrift else {
+        return;
+    };
+    let delivered = row
+        .gelieferte_menge
+        .or_else(|| row_idx.and_then(|r| sheet_number(sheet, layout.gelieferte_menge, r)))
+        .unwrap_or(0.0);
+    row.gelieferte_menge = Some(delivered - credited);
+
+    let reference = row
+        .nummer_gutschrift
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(credit_token)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let col = credit_column(layout, row);
+    let mut existing = match (col, row_idx) {
+        (Some(c), Some(r)) => sheet.get_value((c, r)).trim().to_string(),
+        _ => String::new(),
+    };
+    if existing.is_empty() && col == layout.anmerkungen {
+        existing = row.anmerkungen.clone().unwrap_or_default();
+    }
+    row.nummer_gutschrift = Some(if existing.trim().is_empty() {
+        reference
+    } else {
+        format!("{}; {}", existing.trim(), reference)
+    });
+}
+
+fn reconcile_rows(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    data: &[ExportRow],
+    settings: &ReconcileSettings,
+    catalog: &Catalog,
+) -> ReconciliationReport {
+    let mut lines: Vec<ReconcileLine> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+
+    for r in (layout.header_row + 1)..=sheet.get_highest_row() {
+        let nummer_auftrag = sheet.get_value((layout.nummer_auftrag, r));
+        let produkt = sheet.get_value((layout.produkt, r));
+        let produkt = catalog
+            .canonical_name(&produkt)
+            .map(str::to_string)
+            .unwrap_or(produkt);
+        if nummer_auftrag.trim().is_empty() || produkt.trim().is_empty() {
+            continue;
+        }
+
+        let idx = *index
+            .entry(line_key(&nummer_auftrag, &produkt))
+            .or_insert_with(|| {
+                lines.push(ReconcileLine {
+                    row: Some(r),
+                    nummer_auftrag: nummer_auftrag.trim().to_string(),
+                    produkt: produkt.trim().to_string(),
+                    menge: sheet_number(sheet, layout.menge, r),
+                    preis: sheet_number(sheet, layout.preis, r),
+                    invoices: Vec::new(),
+                });
+                lines.len() - 1
+            });
+
+        let nummer_rechnung = layout
+            .nummer_rechnung
+            .map(|col| sheet.get_value((col, r)).trim().to_string())
+            .filter(|v| !v.is_empty());
+        let gelieferte_menge = sheet_number(sheet, layout.gelieferte_menge, r);
+        if nummer_rechnung.is_some() || gelieferte_menge.is_some() {
+            lines[idx].add_invoice(InvoiceLine {
+                nummer_rechnung,
+                gelieferte_menge,
+                preis: sheet_number(sheet, layout.preis_rechnung, r),
+            });
+        }
+    }
+
+    for row in data {
+        let (Some(nummer_auftrag), Some(produkt)) = (&row.nummer_auftrag, &row.produkt) else {
+            continue;
+        };
+
+        let idx = *index
+            .entry(line_key(nummer_auftrag, produkt))
+            .or_insert_with(|| {
+                lines.push(ReconcileLine {
+                    nummer_auftrag: nummer_auftrag.trim().to_string(),
+                    produkt: produkt.trim().to_string(),
+                    ..Default::default()
+                });
+                lines.len() - 1
+            });
+
+        let line = &mut lines[idx];
+        if line.menge.is_none() {
+            line.menge = row.menge;
+        }
+        if line.preis.is_none() {
+            line.preis = row.preis;
+        }
+        if row.nummer_rechnung.is_some()
+            || row.gelieferte_menge.is_some()
+            || row.preis_rechnung.is_some()
+        {
+            line.add_invoice(InvoiceLine {
+                nummer_rechnung: row.nummer_rechnung.clone(),
+                gelieferte_menge: row.gelieferte_menge,
+                preis: row.preis_rechnung,
+            });
+        }
+        if let Some(credited) = row.menge_gutschrift {
+            line.add_invoice(InvoiceLine {
+                nummer_rechnung: Some(credit_token(
+                    row.nummer_gutschrift.as_deref().unwrap_or_default(),
+                )),
+                gelieferte_menge: Some(-credited),
+                preis: None,
+            });
+        }
+    }
+
+    reconcile::reconcile(&lines, settings)
+}
+
+fn annotate(data: &mut [ExportRow], report: &ReconciliationReport) {
+    for (key, note) in report.new_line_notes() {
+        let target = data.iter_mut().find(|row| {
+            row.nummer_auftrag
+                .as_deref()
+                .zip(row.produkt.as_deref())
+                .is_some_and(|(n, p)| line_key(n, p) == key)
+        });
+
+        if let Some(row) = target {
+            row.anmerkungen = Some(match row.anmerkungen.take() {
+                Some(existing) if !existing.trim().is_empty() => format!("{}; {}", existing, note),
+                _ => note,
+            });
+        }
+    }
+}
+
+// Rewrites the reconciliation notes of existing rows of the exported orders, also
+// clearing notes of discrepancies that have since been resolved. Runs after the row
+// updates are planned so a credit reference written to the same cell is kept.
+fn plan_notes(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    updates: &[RowUpdate],
+    report: &ReconciliationReport,
+    orders: &HashSet<String>,
+) -> Vec<NoteUpdate> {
+    let Some(col) = layout.anmerkungen else {
+        return Vec::new();
+    };
+    let column = column_letter(col);
+    let row_notes = report.row_notes();
+
+    let mut notes = Vec::new();
+    for r in (layout.header_row + 1)..=sheet.get_highest_row() {
+        let order = sheet.get_value((layout.nummer_auftrag, r));
+        if !orders.contains(&order.trim().to_lowercase()) {
+            continue;
+        }
+        let old_value = sheet.get_value((col, r));
+        let current = updates
+            .iter()
+            .filter(|u| u.row == r)
+            .flat_map(|u| &u.changes)
+            .find(|c| c.column == column)
+            .map_or(old_value.as_str(), |c| c.new_value.as_str());
+        let refreshed = reconcile::refresh_notes(current, row_notes.get(&r).map(String::as_str));
+        if refreshed == current {
+            continue;
+        }
+        notes.push(NoteUpdate {
+            row: r,
+            nummer_auftrag: order,
+            produkt: sheet.get_value((layout.produkt, r)),
+            change: CellChange {
+                field: "anmerkungen".to_string(),
+                column: column.clone(),
+                old_value,
+                new_value: refreshed,
+            },
+        });
+    }
+    notes
+}
+
+fn invoice_numbers(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|n| n.trim().to_lowercase())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+fn join_listed(existing: Option<String>, value: Option<String>) -> Option<String> {
+    match (existing, value) {
+        (Some(e), Some(v)) if !e.trim().is_empty() => Some(format!("{}, {}", e.trim(), v.trim())),
+        (e, v) => v.or(e),
+    }
+}
+
+fn add_quantities(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    }
+}
+
+fn is_partial(existing: &ExportRow, row: &ExportRow) -> bool {
+    match (&existing.nummer_rechnung, &row.nummer_rechnung) {
+        (Some(listed), Some(nummer)) => {
+            let listed = invoice_numbers(listed);
+            invoice_numbers(nummer).iter().any(|n| !listed.contains(n))
+        }
+        _ => false,
+    }
+}
+
+fn without_invoice(row: ExportRow) -> ExportRow {
+    ExportRow {
+        nummer_rechnung: None,
+        datum_rechnung: None,
+        gelieferte_menge: None,
+        preis_rechnung: None,
+        ..row
+    }
+}
+
+fn sub_row(order: &ExportRow, invoice: &ExportRow) -> ExportRow {
+    ExportRow {
+        datum_auftrag: order.datum_auftrag.clone(),
+        nummer_auftrag: order.nummer_auftrag.clone(),
+        kunde: order.kunde.clone().or_else(|| invoice.kunde.clone()),
+        lieferant: order
+            .lieferant
+            .clone()
+            .or_else(|| invoice.lieferant.clone()),
+        produkt: order.produkt.clone(),
+        datum_rechnung: invoice.datum_rechnung.clone(),
+        nummer_rechnung: invoice.nummer_rechnung.clone(),
+        gelieferte_menge: invoice.gelieferte_menge,
+        preis_rechnung: invoice.preis_rechnung,
+        document_hash: invoice.document_hash.clone(),
+        ..Default::default()
+    }
+}
+
+fn accumulate_invoice(existing: &mut ExportRow, row: &ExportRow) {
+    existing.gelieferte_menge = add_quantities(existing.gelieferte_menge, row.gelieferte_menge);
+    existing.nummer_rechnung =
+        join_listed(existing.nummer_rechnung.take(), row.nummer_rechnung.clone());
+    existing.datum_rechnung =
+        join_listed(existing.datum_rechnung.take(), row.datum_rechnung.clone());
+    if existing.preis_rechnung.is_none() {
+        existing.preis_rechnung = row.preis_rechnung;
+    }
+}
+
+fn accumulate_sheet_invoice(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    row_idx: u32,
+    row: &mut ExportRow,
+) {
+    let (Some(col), Some(nummer)) = (layout.nummer_rechnung, &row.nummer_rechnung) else {
+        return;
+    };
+    let cell = sheet.get_value((col, row_idx));
+    let listed = invoice_numbers(&cell);
+    let new = invoice_numbers(nummer);
+    if listed.is_empty() {
+        return;
+    }
+    // Already recorded: the sheet quantity may include credit notes applied since.
+    if new.iter().all(|n| listed.contains(n)) {
+        *row = without_invoice(std::mem::take(row));
+        return;
+    }
+
+    let datum = layout
+        .datum_rechnung
+        .map(|c| sheet.get_value((c, row_idx)).trim().to_string())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            parse_date(&v)
+                .map(|d| d.format("%d.%m.%Y").to_string())
+                .unwrap_or(v)
+        });
+    row.nummer_rechnung = join_listed(Some(cell), row.nummer_rechnung.take());
+    row.datum_rechnung = join_listed(datum, row.datum_rechnung.take());
+    row.gelieferte_menge = add_quantities(
+        sheet_number(sheet, layout.gelieferte_menge, row_idx),
+        row.gelieferte_menge,
+    );
+}
+
+fn invoice_row(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    rows: &[u32],
+    claimed: &HashSet<u32>,
+    row: &ExportRow,
+) -> Option<u32> {
+    let cell = |r: u32| {
+        layout
+            .nummer_rechnung
+            .map(|c| sheet.get_value((c, r)).trim().to_lowercase())
+            .unwrap_or_default()
+    };
+    let Some(nummer) = row
+        .nummer_rechnung
+        .as_deref()
+        .filter(|n| !n.trim().is_empty())
+    else {
+        return rows.first().copied();
+    };
+    let nummer = nummer.trim().to_lowercase();
+    rows.iter()
+        .copied()
+        .find(|&r| cell(r) == nummer)
+        .or_else(|| {
+            rows.iter()
+                .copied()
+                .find(|&r| cell(r).is_empty() && !claimed.contains(&r))
+        })
+}
+
+fn plan_update(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    row_idx: u32,
+    mut row: ExportRow,
+) -> RowUpdate {
+    resolve_credit(sheet, layout, Some(row_idx), &mut row);
+    let credit_col = credit_column(layout, &row);
+    let mut changes = vec![
+        text_change(
+            sheet,
+            "datumRechnung",
+            layout.datum_rechnung,
+            row_idx,
+            &row.datum_rechnung,
+        ),
+        text_change(
+            sheet,
+            "nummerRechnung",
+            layout.nummer_rechnung,
+            row_idx,
+            &row.nummer_rechnung,
+        ),
+        number_change(
+            sheet,
+            "gelieferteMenge",
+            layout.gelieferte_menge,
+            row_idx,
+            row.gelieferte_menge,
+        ),
+        number_change(
+            sheet,
+            "preisRechnung",
+            layout.preis_rechnung,
+            row_idx,
+            row.preis_rechnung,
+        ),
+        text_change(
+            sheet,
+            "nummerDdt",
+            layout.nummer_ddt,
+            row_idx,
+            &row.nummer_ddt,
+        ),
+        text_change(sheet, "datumDdt", layout.datum_ddt, row_idx, &row.datum_ddt),
+        number_change(sheet, "mengeDdt", layout.menge_ddt, row_idx, row.menge_ddt),
+        text_change(
+            sheet,
+            "nummerGutschrift",
+            credit_col,
+            row_idx,
+            &row.nummer_gutschrift,
+        ),
+    ];
+    for (field, col, value) in [
+        ("artikelnummer", layout.artikelnummer, &row.artikelnummer),
+        ("einheit", layout.einheit, &row.einheit),
+        (
+            "anmerkungen",
+            layout.anmerkungen.filter(|c| credit_col != Some(*c)),
+            &row.anmerkungen,
+        ),
+    ] {
+        if col.is_some_and(|c| sheet.get_value((c, row_idx)).is_empty()) {
+            changes.push(text_change(sheet, field, col, row_idx, value));
+        }
+    }
+
+    RowUpdate {
+        row: row_idx,
+        nummer_auftrag: sheet.get_value((layout.nummer_auftrag, row_idx)),
+        produkt: sheet.get_value((layout.produkt, row_idx)),
+        changes: changes.into_iter().flatten().collect(),
+        data: row,
+    }
+}
+
+fn match_products(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    index_map: &HashMap<(String, String), Vec<u32>>,
+    data: Vec<ExportRow>,
+    matcher: &ProductMatcher,
+    catalog: &Catalog,
+) -> (Vec<ExportRow>, Vec<AmbiguousMatch>, Vec<FuzzyMatch>) {
+    let mut by_order: HashMap<&str, Vec<(u32, String)>> = HashMap::new();
+    for ((auftrag_nr, _), rows) in index_map {
+        let produkt = sheet.get_value((layout.produkt, rows[0]));
+        let produkt = catalog
+            .canonical_name(&produkt)
+            .unwrap_or(&produkt)
+            .trim()
+            .to_string();
+        by_order
+            .entry(auftrag_nr.as_str())
+            .or_default()
+            .push((rows[0], produkt));
+    }
+
+    let mut matched = Vec::with_capacity(data.len());
+    let mut ambiguous = Vec::new();
+    let mut fuzzy = Vec::new();
+    for mut row in data {
+        let (Some(nr), Some(prod)) = (&row.nummer_auftrag, &row.produkt) else {
+            matched.push(row);
+            continue;
+        };
+        let key = (nr.trim().to_lowercase(), prod.trim().to_lowercase());
+        let Some(candidates) = by_order.get(key.0.as_str()) else {
+            matched.push(row);
+            continue;
+        };
+        if index_map.contains_key(&key) {
+            matched.push(row);
+            continue;
+        }
+
+        match matcher.find(prod, candidates) {
+            ProductMatch::Found(candidate) => {
+                fuzzy.push(FuzzyMatch {
+                    nummer_auftrag: nr.clone(),
+                    produkt: prod.clone(),
+                    candidate: candidate.clone(),
+                });
+                row.produkt = Some(candidate.produkt);
+                matched.push(row);
+            }
+            ProductMatch::Ambiguous(candidates) => {
+                ambiguous.push(AmbiguousMatch { row, candidates })
+            }
+            ProductMatch::NotFound => matched.push(row),
+        }
+    }
+    (matched, ambiguous, fuzzy)
+}
+
+fn unmapped_warnings(layout: &SheetLayout, data: &[ExportRow]) -> Vec<String> {
+    let count = |has_value: fn(&ExportRow) -> bool| data.iter().filter(|r| has_value(r)).count();
+    let fields = [
+        (
+            "Numero DDT",
+            layout.nummer_ddt,
+            count(|r| r.nummer_ddt.is_some()),
+        ),
+        (
+            "Data DDT",
+            layout.datum_ddt,
+            count(|r| r.datum_ddt.is_some()),
+        ),
+        (
+            "Quantità DDT",
+            layout.menge_ddt,
+            count(|r| r.menge_ddt.is_some()),
+        ),
+        (
+            "Prezzo fattura",
+            layout.preis_rechnung,
+            count(|r| r.preis_rechnung.is_some()),
+        ),
+        (
+            "Nota di credito",
+            layout.nummer_gutschrift.or(layout.anmerkungen),
+            count(|r| r.nummer_gutschrift.is_some() || r.menge_gutschrift.is_some()),
+        ),
+        (
+            "Codice articolo",
+            layout.artikelnummer,
+            count(|r| r.artikelnummer.is_some()),
+        ),
+        (
+            "Unità di misura",
+            layout.einheit,
+            count(|r| r.einheit.is_some()),
+        ),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|&(_, col, rows)| col.is_none() && rows > 0)
+        .map(|(label, _, rows)| {
+            format!(
+                "{}: colonna non mappata, valore di {} righe non scritto",
+                label, rows
+            )
+        })
+        .collect()
+}
+
+fn plan_export(
+    sheet: &Worksheet,
+    layout: &SheetLayout,
+    mut data: Vec<ExportRow>,
+    options: &ExportOptions,
+) -> ExportDiff {
+    let highest_row = sheet.get_highest_row();
+    let start_data_row = layout.header_row + 1;
+
+    let mut index_map: HashMap<(String, String), Vec<u32>> = HashMap::new();
+    let mut existing_rows_for_sorting: Vec<SheetRow> = Vec::with_capacity(highest_row as usize);
+
+    if highest_row >= start_data_row {
+        for r in start_data_row..=highest_row {
+            let s_val = layout
+                .lieferant
+                .map(|col| sheet.get_value((col, r)))
+                .unwrap_or_default();
+            let d_val = layout
+                .datum_auftrag
+                .map(|col| sheet.get_value((col, r)))
+                .unwrap_or_default();
+
+            existing_rows_for_sorting.push(SheetRow {
+                row_idx: r,
+                supplier: s_val.to_lowercase(),
+                date: parse_date(&d_val).unwrap_or(NaiveDate::from_ymd_opt(2200, 1, 1).unwrap()),
+            });
+
+            let auftrag_nr = sheet
+                .get_value((layout.nummer_auftrag, r))
+                .trim()
+                .to_lowercase();
+            let produkt = sheet.get_value((layout.produkt, r));
+            let produkt = options
+                .catalog
+                .canonical_name(&produkt)
+                .unwrap_or(&produkt)
+                .trim()
+                .to_lowercase();
+
+            if !auftrag_nr.is_empty() && !produkt.is_empty() {
+                index_map.entry((auftrag_nr, produkt)).or_default().push(r);
+            }
+        }
+    }
+
+    for row in data.iter_mut() {
+        options.catalog.canonicalize(row);
+    }
+
+    let warnings = unmapped_warnings(layout, &data);
+
+    let (mut ambiguous, mut fuzzy_matches) = (Vec::new(), Vec::new());
+    if let Some(matcher) = &options.product_matcher {
+        (data, ambiguous, fuzzy_matches) =
+            match_products(sheet, layout, &index_map, data, matcher, &options.catalog);
+    }
+
+    for row in data.iter_mut() {
+        let (Some(nr), Some(prod), Some(nummer)) =
+            (&row.nummer_auftrag, &row.produkt, &row.nummer_gutschrift)
+        else {
+            continue;
+        };
+        let key = (nr.trim().to_lowercase(), prod.trim().to_lowercase());
+        if index_map.get(&key).is_some_and(|rows| {
+            rows.iter()
+                .any(|&r| credit_recorded(sheet, layout, r, nummer))
+        }) {
+            row.nummer_gutschrift = None;
+            row.menge_gutschrift = None;
+        }
+    }
+
+    let report = options.reconciliation.as_ref().map(|settings| {
+        let report = reconcile_rows(sheet, layout, &data, settings, &options.catalog);
+        if settings.write_notes {
+            annotate(&mut data, &report);
+        }
+        report
+    });
+    let write_notes = options
+        .reconciliation
+        .as_ref()
+        .is_some_and(|settings| settings.write_notes);
+    let exported_orders: HashSet<String> = data
+        .iter()
+        .filter_map(|row| row.nummer_auftrag.as_deref())
+        .map(|nr| nr.trim().to_lowercase())
+        .collect();
+
+    let mut merged_input_map: HashMap<(String, String), ExportRow> = HashMap::new();
+    let mut partial_rows: HashMap<(String, String), Vec<ExportRow>> = HashMap::new();
+    let mut unmatchable_rows: Vec<ExportRow> = Vec::new();
+
+    for mut row in data {
+        if let (Some(nr), Some(prod)) = (&row.nummer_auftrag, &row.produkt) {
+            let key = (nr.trim().to_lowercase(), prod.trim().to_lowercase());
+
+            if let Some(existing) = merged_input_map.get_mut(&key) {
+                if is_partial(existing, &row) {
+                    match options.partial_invoices {
+                        PartialInvoices::Accumulate => accumulate_invoice(existing, &row),
+                        PartialInvoices::SubRows => partial_rows
+                            .entry(key.clone())
+                            .or_default()
+                            .push(sub_row(existing, &row)),
+                    }
+                    row = without_invoice(row);
+                }
+                if let Some(credited) = row.menge_gutschrift {
+                    existing.menge_gutschrift =
+                        Some(existing.menge_gutschrift.unwrap_or(0.0) + credited);
+                }
+                if let Some(nummer) = &row.nummer_gutschrift {
+                    existing.nummer_gutschrift = Some(match existing.nummer_gutschrift.take() {
+                        Some(previous) => format!("{}, {}", previous, nummer),
+                        None => nummer.clone(),
+                    });
+                }
+                if existing.datum_rechnung.is_none() {
+                    existing.datum_rechnung = row.datum_rechnung;
+                }
+                if existing.nummer_rechnung.is_none() {
+                    existing.nummer_rechnung = row.nummer_rechnung;
+                }
+                if existing.gelieferte_menge.is_none() {
+                    existing.gelieferte_menge = row.gelieferte_menge;
+                }
+                if existing.preis.is_none() {
+                    existing.preis = row.preis;
+                }
+                if existing.menge.is_none() {
+                    existing.menge = row.menge;
+                }
+                if existing.preis_rechnung.is_none() {
+                    existing.preis_rechnung = row.preis_rechnung;
+                }
+                if existing.nummer_ddt.is_none() {
+                    existing.nummer_ddt = row.nummer_ddt;
+                }
+                if existing.datum_ddt.is_none() {
+                    existing.datum_ddt = row.datum_ddt;
+                }
+                if existing.menge_ddt.is_none() {
+                    existing.menge_ddt = row.menge_ddt;
+                }
+                if existing.artikelnummer.is_none() {
+                    existing.artikelnummer = row.artikelnummer;
+                }
+                if existing.einheit.is_none() {
+                    existing.einheit = row.einheit;
+                }
+            } else {
+                merged_input_map.insert(key, row);
+            }
+        } else {
+            unmatchable_rows.push(row);
+        }
+    }
+
+    let mut diff = ExportDiff {
+        header_row: layout.header_row,
+        unmatchable: unmatchable_rows.clone(),
+        ambiguous,
+        fuzzy_matches,
+        reconciliation: report,
+        warnings,
+        ..Default::default()
+    };
+
+    let mut processing_queue: Vec<ExportRow> = merged_input_map.into_values().collect();
+    processing_queue.append(&mut unmatchable_rows);
+
+    let mut rows_to_insert: Vec<ExportRow> = Vec::new();
+    let mut claimed: HashSet<u32> = HashSet::new();
+
+    for row in processing_queue {
+        let key = (
+            row.nummer_auftrag
+                .clone()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase(),
+            row.produkt
+                .clone()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase(),
+        );
+
+        let partials = partial_rows.remove(&key).unwrap_or_default();
+        let Some(rows) = index_map.get(&key) else {
+            for mut row in std::iter::once(row).chain(partials) {
+                resolve_credit(sheet, layout, None, &mut row);
+                rows_to_insert.push(row);
+            }
+            continue;
+        };
+
+        match options.partial_invoices {
+            PartialInvoices::Accumulate => {
+                let row_idx = rows[rows.len() - 1];
+                let mut row = row;
+                accumulate_sheet_invoice(sheet, layout, row_idx, &mut row);
+                diff.push_update(plan_update(sheet, layout, row_idx, row));
+            }
+            PartialInvoices::SubRows => {
+                let mut sub_rows = Vec::new();
+                for (i, row) in std::iter::once(row).chain(partials).enumerate() {
+                    match invoice_row(sheet, layout, rows, &claimed, &row) {
+                        Some(row_idx) => {
+                            claimed.insert(row_idx);
+                            diff.push_update(plan_update(sheet, layout, row_idx, row));
+                        }
+                        None if i == 0 => {
+                            sub_rows.push(sub_row(&row, &row));
+                            claimed.insert(rows[0]);
+                            diff.push_update(plan_update(
+                                sheet,
+                                layout,
+                                rows[0],
+                                without_invoice(row),
+                            ));
+                        }
+                        None => sub_rows.push(row),
+                    }
+                }
+                let before_row = rows[rows.len() - 1] + 1;
+                for mut row in sub_rows {
+                    resolve_credit(sheet, layout, None, &mut row);
+                    diff.insertions.push(RowInsertion { before_row, row });
+                }
+            }
+        }
+    }
+
+    if let Some(report) = diff.reconciliation.as_ref().filter(|_| write_notes) {
+        diff.notes = plan_notes(sheet, layout, &diff.updates, report, &exported_orders);
+    }
+
+    rows_to_insert.sort_by(|a, b| {
+        let date_a = parse_date(&a.datum_auftrag.clone().unwrap_or_default());
+        let date_b = parse_date(&b.datum_auftrag.clone().unwrap_or_default());
+        date_a.cmp(&date_b)
+    });
+
+    for new_row in rows_to_insert {
+        let target_supplier = new_row.lieferant.clone().unwrap_or_default().to_lowercase();
+        let target_date = parse_date(&new_row.datum_auftrag.clone().unwrap_or_default())
+            .unwrap_or(NaiveDate::from_ymd_opt(1900, 1, 1).unwrap());
+
+        let mut insert_at = highest_row + 1;
+        if insert_at < start_data_row {
+            insert_at = start_data_row;
+        }
+
+        let mut found_supplier_block = false;
+        for ex in &existing_rows_for_sorting {
+            if ex.supplier == target_supplier {
+                found_supplier_block = true;
+                if ex.date > target_date {
+                    insert_at = ex.row_idx;
+                    break;
+                }
+            } else if found_supplier_block || ex.supplier > target_supplier {
+                insert_at = ex.row_idx;
+                break;
+            }
+        }
+
+        diff.insertions.push(RowInsertion {
+            before_row: insert_at,
+            row: new_row,
+        });
+    }
+
+    diff
+}
+
+fn apply_export(
+    sheet: &mut Worksheet,
+    layout: &SheetLayout,
+    diff: &ExportDiff,
+    progress: &dyn Fn(usize, usize),
+) {
+    let start_data_row = layout.header_row + 1;
+    let total_ops = diff.updates.len() + diff.notes.len() + diff.insertions.len();
+    let mut current_progress = 0;
+
+    for update in &diff.updates {
+        let row_idx = update.row;
+        let row = &update.data;
+
+        set_text(sheet, layout.datum_rechnung, row_idx, &row.datum_rechnung);
+        set_text(sheet, layout.nummer_rechnung, row_idx, &row.nummer_rechnung);
+        set_number(
+            sheet,
+            layout.gelieferte_menge,
+            row_idx,
+            row.gelieferte_menge,
+        );
+        set_number(sheet, layout.preis_rechnung, row_idx, row.preis_rechnung);
+        set_text(sheet, layout.nummer_ddt, row_idx, &row.nummer_ddt);
+        set_text(sheet, layout.datum_ddt, row_idx, &row.datum_ddt);
+        set_number(sheet, layout.menge_ddt, row_idx, row.menge_ddt);
+        let credit_col = credit_column(layout, row);
+        for (col, value) in [
+            (layout.artikelnummer, &row.artikelnummer),
+            (layout.einheit, &row.einheit),
+            (
+                layout.anmerkungen.filter(|c| credit_col != Some(*c)),
+                &row.anmerkungen,
+            ),
+        ] {
+            if col.is_some_and(|c| sheet.get_value((c, row_idx)).is_empty()) {
+                set_text(sheet, col, row_idx, value);
+            }
+        }
+        set_text(sheet, credit_col, row_idx, &row.nummer_gutschrift);
+
+        current_progress += 1;
+        if current_progress % 10 == 0 || current_progress == total_ops {
+            progress(current_progress, total_ops);
+        }
+    }
+
+    for note in &diff.notes {
+        if let Some(col) = layout.anmerkungen {
+            sheet
+                .get_cell_mut((col, note.row))
+                .set_value(note.change.new_value.clone());
+        }
+
+        current_progress += 1;
+        if current_progress % 10 == 0 || current_progress == total_ops {
+            progress(current_progress, total_ops);
+        }
+    }
+
+    let mut insertions: BTreeMap<u32, Vec<&ExportRow>> = BTreeMap::new();
+    for insertion in &diff.insertions {
+        insertions
+            .entry(insertion.before_row)
+            .or_default()
+            .push(&insertion.row);
+    }
+
+    let last_column = layout.last_column();
+
+    for (row_idx, batch) in insertions.iter().rev() {
+        let start_row = *row_idx;
+        let count = batch.len() as u32;
+
+        sheet.insert_new_row(&start_row, &count);
+
+        let (template_row, formula_source_row) = if start_row > start_data_row {
+            (start_row - 1, start_row - 1)
+        } else {
+            (start_row + count, start_row + count)
+        };
+
+        let template_formula = layout
+            .formel
+            .and_then(|col| sheet.get_cell((col, template_row)))
+            .map(|c| c.get_formula().to_string())
+            .unwrap_or_default();
+
+        let mut column_styles = Vec::with_capacity(last_column as usize);
+        for col in 1..=last_column {
+            column_styles.push(sheet.get_style((col, template_row)).clone());
+        }
+
+        for (i, row_data) in batch.iter().enumerate() {
+            let r = start_row + i as u32;
+
+            set_text(sheet, layout.datum_auftrag, r, &row_data.datum_auftrag);
+            set_text(
+                sheet,
+                Some(layout.nummer_auftrag),
+                r,
+                &row_data.nummer_auftrag,
+            );
+            set_text(sheet, layout.kunde, r, &row_data.kunde);
+            set_text(sheet, layout.lieferant, r, &row_data.lieferant);
+            set_text(sheet, Some(layout.produkt), r, &row_data.produkt);
+            set_text(sheet, layout.artikelnummer, r, &row_data.artikelnummer);
+            set_text(sheet, layout.einheit, r, &row_data.einheit);
+            set_number(sheet, layout.menge, r, row_data.menge);
+            set_text(sheet, layout.waehrung, r, &row_data.waehrung);
+            set_number(sheet, layout.preis, r, row_data.preis);
+            set_text(sheet, layout.datum_rechnung, r, &row_data.datum_rechnung);
+            set_text(sheet, layout.nummer_rechnung, r, &row_data.nummer_rechnung);
+            set_number(sheet, layout.gelieferte_menge, r, row_data.gelieferte_menge);
+            set_number(sheet, layout.preis_rechnung, r, row_data.preis_rechnung);
+            set_text(sheet, layout.nummer_ddt, r, &row_data.nummer_ddt);
+            set_text(sheet, layout.datum_ddt, r, &row_data.datum_ddt);
+            set_number(sheet, layout.menge_ddt, r, row_data.menge_ddt);
+            set_text(sheet, layout.anmerkungen, r, &row_data.anmerkungen);
+            set_text(
+                sheet,
+                credit_column(layout, row_data),
+                r,
+                &row_data.nummer_gutschrift,
+            );
+
+            for col in 1..=last_column {
+                if let Some(style) = column_styles.get((col - 1) as usize) {
+                    let mut s = style.clone();
+
+                    if layout.is_date_column(col) {
+                        s.get_alignment_mut()
+                            .set_horizontal(umya_spreadsheet::HorizontalAlignmentValues::Right);
+                    }
+
+                    sheet.set_style((col, r), s);
+                }
+            }
+
+            if let Some(col) = layout.formel.filter(|_| !template_formula.is_empty()) {
+                let new_formula = adjust_formula(&template_formula, formula_source_row, r);
+                sheet.get_cell_mut((col, r)).set_formula(new_formula);
+            }
+
+            current_progress += 1;
+            if current_progress % 10 == 0 || current_progress == total_ops {
+                progress(current_progress, total_ops);
+            }
+        }
+    }
+
+    progress(total_ops, total_ops);
+}
+
+pub fn replace_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> Result<(), String>,
+) -> Result<(), String> {
+    let file_name = path
+        .file_name()
+        .ok_or("Nome file Excel non valido.".to_string())?;
+    let tmp_path = path.with_file_name(format!(".{}.maggus-tmp", file_name.to_string_lossy()));
+
+    let result = File::create(&tmp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all().map_err(|e| e.to_string())
+        })
+        .and_then(|_| fs::rename(&tmp_path, path).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Errore di memoria: {}", e));
+    }
+    Ok(())
+}
+
+fn write_atomic(book: &Spreadsheet, path: &Path) -> Result<(), String> {
+    replace_atomic(path, |file| {
+        umya_spreadsheet::writer::xlsx::write_writer(book, file).map_err(|e| e.to_string())
+    })
+}
+
+pub fn ensure_writable(path: &Path) -> Result<(), String> {
+    if let Some(lock) = workbook_lock::find_lock(path) {
+        return Err(lock.message());
+    }
+    if OpenOptions::new().append(true).open(path).is_err() {
+        return Err("Accesso negato! Il file è aperto.".to_string());
+    }
+    Ok(())
+}
+
+pub fn export_rows(
+    path: &Path,
+    data: Vec<ExportRow>,
+    mapping: &ColumnMapping,
+    options: &ExportOptions,
+    progress: &dyn Fn(usize, usize),
+) -> Result<ExportDiff, String> {
+    if !options.dry_run {
+        ensure_writable(path)?;
+    }
+
+    let mut book = umya_spreadsheet::reader::xlsx::read(path)
+        .map_err(|e| format!("Errore di lettura: {}", e))?;
+
+    let sheet = book
+        .get_sheet_mut(&0)
+        .ok_or("Nessun foglio di lavoro trovato.".to_string())?;
+
+    let layout = mapping.resolve(sheet)?;
+    let diff = plan_export(sheet, &layout, data, options);
+
+    if options.dry_run {
+        return Ok(diff);
+    }
+
+    apply_export(sheet, &layout, &diff, progress);
+
+    write_atomic(&book, path)?;
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header in row 1 as in the default mapping: A date, B order, D supplier, E product,
+    // F quantity, H price, J/K/L invoice date/number/quantity, R notes.
+    fn sheet(rows: &[(&str, &str, f64)]) -> (Spreadsheet, SheetLayout) {
+        let mut book = umya_spreadsheet::new_file();
+        let sheet = book.get_sheet_mut(&0).unwrap();
+        sheet.get_cell_mut("D1").set_value("Casa Estera");
+        for (i, (nummer, produkt, menge)) in rows.iter().enumerate() {
+            let r = i as u32 + 2;
+            sheet.get_cell_mut((1, r)).set_value("01.10.2026");
+            sheet.get_cell_mut((2, r)).set_value(*nummer);
+            sheet.get_cell_mut((4, r)).set_value("Rossi");
+            sheet.get_cell_mut((5, r)).set_value(*produkt);
+            sheet.get_cell_mut((6, r)).set_value_number(*menge);
+            sheet.get_cell_mut((8, r)).set_value_number(10.0);
+        }
+        let layout = ColumnMapping::default().resolve(sheet).unwrap();
+        (book, layout)
+    }
+
+    fn invoice(nummer: &str, produkt: &str, gelieferte_menge: f64) -> ExportRow {
+        ExportRow {
+            nummer_auftrag: Some(nummer.to_string()),
+            produkt: Some(produkt.to_string()),
+            datum_rechnung: Some("05.10.2026".to_string()),
+            nummer_rechnung: Some("F-1".to_string()),
+            gelieferte_menge: Some(gelieferte_menge),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rows_already_up_to_date_are_not_counted_as_updated() {
+        let (mut book, layout) = sheet(&[("A-1", "Vite M8", 100.0)]);
+        let options = ExportOptions::default();
+        let data = vec![invoice("A-1", "Vite M8", 100.0)];
+
+        let diff = plan_export(book.get_sheet(&0).unwrap(), &layout, data.clone(), &options);
+        assert_eq!(diff.updates.len(), 1);
+        apply_export(book.get_sheet_mut(&0).unwrap(), &layout, &diff, &|_, _| {});
+
+        let diff = plan_export(book.get_sheet(&0).unwrap(), &layout, data, &options);
+        assert!(diff.updates.is_empty());
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn fuzzy_product_matches_are_reported_in_the_diff() {
+        let (book, layout) = sheet(&[("A-1", "Vite M8 zincata", 100.0)]);
+        let options = ExportOptions {
+            product_matcher: Some(ProductMatcher::new(Default::default(), &HashMap::new())),
+            ..Default::default()
+        };
+
+        let diff = plan_export(
+            book.get_sheet(&0).unwrap(),
+            &layout,
+            vec![invoice("A-1", "Vite zincata M8", 100.0)],
+            &options,
+        );
+        assert_eq!(diff.fuzzy_matches.len(), 1);
+        assert_eq!(diff.fuzzy_matches[0].produkt, "Vite zincata M8");
+        assert_eq!(diff.fuzzy_matches[0].candidate.row, 2);
+        assert_eq!(diff.updates.len(), 1);
+        assert!(diff.insertions.is_empty());
+    }
+
+    #[test]
+    fn unmapped_columns_are_reported_instead_of_silently_skipped() {
+        let (book, layout) = sheet(&[("A-1", "Vite M8", 100.0)]);
+        let data = vec![ExportRow {
+            nummer_ddt: Some("DDT 7".to_string()),
+            menge_ddt: Some(40.0),
+            preis_rechnung: Some(10.0),
+            ..invoice("A-1", "Vite M8", 40.0)
+        }];
+
+        let diff = plan_export(
+            book.get_sheet(&0).unwrap(),
+            &layout,
+            data,
+            &ExportOptions::default(),
+        );
+        assert_eq!(diff.warnings.len(), 3);
+        assert!(diff.warnings[0].starts_with("Numero DDT"));
+        assert!(diff.warnings[1].starts_with("Quantità DDT"));
+        assert!(diff.warnings[2].starts_with("Prezzo fattura"));
+    }
+
+    fn reconciled() -> ExportOptions {
+        ExportOptions {
+            reconciliation: Some(ReconcileSettings::default()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn findings_on_existing_rows_are_note_only_updates() {
+        let (mut book, layout) = sheet(&[
+            ("A-1", "Vite M8", 100.0),
+            ("A-1", "Tassello", 20.0),
+            ("A-2", "Chiodo", 10.0),
+        ]);
+        let anmerkungen = layout.anmerkungen.unwrap();
+        let sheet = book.get_sheet_mut(&0).unwrap();
+        sheet.get_cell_mut((anmerkungen, 2)).set_value("Urgente");
+        sheet
+            .get_cell_mut((anmerkungen, 3))
+            .set_value("Consegna parziale concordata; ⚠ Consegna parziale: 5 di 20");
+        sheet
+            .get_cell_mut((anmerkungen, 4))
+            .set_value("⚠ Consegna parziale: 1 di 10");
+
+        let data = vec![invoice("A-1", "Vite M8", 100.0)];
+        let diff = plan_export(book.get_sheet(&0).unwrap(), &layout, data, &reconciled());
+
+        // The invoice only touches row 2; row 3 is now missing an invoice and its
+        // stale quantity note is replaced. Order A-2 is not part of this export.
+        assert_eq!(diff.updates.len(), 1);
+        assert!(diff.updates[0]
+            .changes
+            .iter()
+            .all(|c| c.field != "anmerkungen"));
+        assert_eq!(diff.notes.len(), 1);
+        assert_eq!(diff.notes[0].row, 3);
+        assert_eq!(
+            diff.notes[0].change.new_value,
+            "Consegna parziale concordata; ⚠ Fattura mancante"
+        );
+
+        apply_export(book.get_sheet_mut(&0).unwrap(), &layout, &diff, &|_, _| {});
+        let sheet = book.get_sheet(&0).unwrap();
+        assert_eq!(sheet.get_value((anmerkungen, 2)), "Urgente");
+        assert_eq!(
+            sheet.get_value((anmerkungen, 3)),
+            "Consegna parziale concordata; ⚠ Fattura mancante"
+        );
+        assert_eq!(
+            sheet.get_value((anmerkungen, 4)),
+            "⚠ Consegna parziale: 1 di 10"
+        );
+
+        let data = vec![invoice("A-1", "Tassello", 20.0)];
+        let diff = plan_export(sheet, &layout, data, &reconciled());
+        assert_eq!(diff.notes.len(), 1);
+        assert_eq!(
+            diff.notes[0].change.new_value,
+            "Consegna parziale concordata"
+        );
+    }
+
+    #[test]
+    fn notes_keep_credit_references_written_to_the_same_cell() {
+        let (mut book, layout) = sheet(&[("A-1", "Vite M8", 100.0)]);
+        let mut credit = invoice("A-1", "Vite M8", 100.0);
+        credit.nummer_gutschrift = Some("7".to_string());
+        credit.menge_gutschrift = Some(10.0);
+
+        let diff = plan_export(
+            book.get_sheet(&0).unwrap(),
+            &layout,
+            vec![credit],
+            &reconciled(),
+        );
+        assert_eq!(diff.notes.len(), 1);
+        assert_eq!(
+            diff.notes[0].change.new_value,
+            "NC 7; ⚠ Consegna parziale: 90 di 100"
+        );
+
+        apply_export(book.get_sheet_mut(&0).unwrap(), &layout, &diff, &|_, _| {});
+        let sheet = book.get_sheet(&0).unwrap();
+        assert_eq!(
+            sheet.get_value((layout.anmerkungen.unwrap(), 2)),
+            "NC 7; ⚠ Consegna parziale: 90 di 100"
+        );
+    }
+
+    #[test]
+    fn re_exporting_an_invoice_keeps_credited_quantities() {
+        let (mut book, layout) = sheet(&[("A-1", "Vite M8", 100.0)]);
+        let options = ExportOptions::default();
+        let export = |book: &mut Spreadsheet, data: Vec<ExportRow>| {
+            let diff = plan_export(book.get_sheet(&0).unwrap(), &layout, data, &options);
+            apply_export(book.get_sheet_mut(&0).unwrap(), &layout, &diff, &|_, _| {});
+        };
+        let delivered = |book: &Spreadsheet| {
+            sheet_number(book.get_sheet(&0).unwrap(), layout.gelieferte_menge, 2)
+        };
+
+        export(&mut book, vec![invoice("A-1", "Vite M8", 100.0)]);
+        assert_eq!(delivered(&book), Some(100.0));
+
+        let credit = ExportRow {
+            nummer_auftrag: Some("A-1".to_string()),
+            produkt: Some("Vite M8".to_string()),
+            nummer_gutschrift: Some("7".to_string()),
+            menge_gutschrift: Some(10.0),
+            ..Default::default()
+        };
+        export(&mut book, vec![credit]);
+        assert_eq!(delivered(&book), Some(90.0));
+
+        export(&mut book, vec![invoice("A-1", "Vite M8", 100.0)]);
+        assert_eq!(delivered(&book), Some(90.0));
+    }
+}
@@ -0,0 +1,390 @@
+use serde_json::{json, Value};
+
+use crate::catalog::CatalogMatch;
+use crate::classify::{Classification, DocumentKind};
+use crate::corrections::AppliedCorrection;
+use crate::documents::DocumentStatus;
+
+pub const SCHEMA_VERSION: u32 = 8;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OrderProduct {
+    pub produkt: String,
+    pub artikelnummer: Option<String>,
+    pub einheit: Option<String>,
+    pub menge: Option<f64>,
+    pub waehrung: Option<String>,
+    pub preis: Option<f64>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OrderExtraction {
+    pub produkte: Vec<OrderProduct>,
+    pub nummer_auftrag: Option<String>,
+    pub datum_auftrag: Option<String>,
+    pub lieferant: Option<String>,
+    pub kunde: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InvoiceProduct {
+    pub produkt: String,
+    pub artikelnummer: Option<String>,
+    pub einheit: Option<String>,
+    pub gelieferte_menge: Option<f64>,
+    pub preis: Option<f64>,
+    pub nummer_auftrag: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InvoiceExtraction {
+    pub produkte: Vec<InvoiceProduct>,
+    pub nummer_rechnung: Option<String>,
+    pub datum_rechnung: Option<String>,
+    pub lieferant: Option<String>,
+    pub kunde: Option<String>,
+    #[serde(default)]
+    pub auftraege: Vec<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CreditNoteExtraction {
+    pub produkte: Vec<InvoiceProduct>,
+    pub nummer_gutschrift: Option<String>,
+    pub datum_gutschrift: Option<String>,
+    pub lieferant: Option<String>,
+    pub kunde: Option<String>,
+    #[serde(default)]
+    pub auftraege: Vec<String>,
+    #[serde(default)]
+    pub rechnungen: Vec<String>,
+}
+
+impl InvoiceExtraction {
+    pub fn into_credit_note(self, rechnungen: Vec<String>) -> CreditNoteExtraction {
+        CreditNoteExtraction {
+            produkte: self.produkte,
+            nummer_gutschrift: self.nummer_rechnung,
+            datum_gutschrift: self.datum_rechnung,
+            lieferant: self.lieferant,
+            kunde: self.kunde,
+            auftraege: self.auftraege,
+            rechnungen,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeliveryProduct {
+    pub produkt: String,
+    pub artikelnummer: Option<String>,
+    pub einheit: Option<String>,
+    pub gelieferte_menge: Option<f64>,
+    pub nummer_auftrag: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeliveryNoteExtraction {
+    pub produkte: Vec<DeliveryProduct>,
+    pub nummer_ddt: Option<String>,
+    pub datum_ddt: Option<String>,
+    pub lieferant: Option<String>,
+    pub kunde: Option<String>,
+    #[serde(default)]
+    pub auftraege: Vec<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(tag = "docType", rename_all = "camelCase")]
+pub enum Extraction {
+    Auftrag(OrderExtraction),
+    Rechnung(InvoiceExtraction),
+    Lieferschein(DeliveryNoteExtraction),
+    Gutschrift(CreditNoteExtraction),
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalysisResult {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub extraction: Extraction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<DocumentStatus>,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mismatches: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classification: Option<Classification>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub corrections: Vec<AppliedCorrection>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub catalog: Vec<CatalogMatch>,
+    // Extraction before corrections and catalog, stored in the document archive so
+    // cached results go through the current rules again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncorrected: Option<Box<Extraction>>,
+}
+
+impl From<Extraction> for AnalysisResult {
+    fn from(extraction: Extraction) -> Self {
+        AnalysisResult {
+            schema_version: SCHEMA_VERSION,
+            extraction,
+            document: None,
+            retries: 0,
+            mismatches: Vec::new(),
+            classification: None,
+            corrections: Vec::new(),
+            catalog: Vec::new(),
+            uncorrected: None,
+        }
+    }
+}
+
+impl Extraction {
+    pub fn parse(doc_type: &str, value: Value) -> Result<Extraction, String> {
+        let extraction = match doc_type {
+            "rechnung" => serde_json::from_value(value)
+                .map(Extraction::Rechnung)
+                .map_err(|e| format!("Risposta fattura non valida: {}", e))?,
+            "lieferschein" => serde_json::from_value(value)
+                .map(Extraction::Lieferschein)
+                .map_err(|e| format!("Risposta DDT non valida: {}", e))?,
+            "gutschrift" => serde_json::from_value(value)
+                .map(Extraction::Gutschrift)
+                .map_err(|e| format!("Risposta nota di credito non valida: {}", e))?,
+            _ => serde_json::from_value(value)
+                .map(Extraction::Auftrag)
+                .map_err(|e| format!("Risposta ordine non valida: {}", e))?,
+        };
+
+        if let Some(name) = extraction.product_names().find(|n| n.trim().is_empty()) {
+            return Err(format!(
+                "Risposta non valida: prodotto senza nome ({:?}).",
+                name
+            ));
+        }
+
+        Ok(extraction)
+    }
+
+    pub fn kind(&self) -> DocumentKind {
+        match self {
+            Extraction::Auftrag(_) => DocumentKind::Auftrag,
+            Extraction::Rechnung(_) => DocumentKind::Rechnung,
+            Extraction::Lieferschein(_) => DocumentKind::Lieferschein,
+            Extraction::Gutschrift(_) => DocumentKind::Gutschrift,
+        }
+    }
+
+    pub fn has_products(&self) -> bool {
+        self.product_names().next().is_some()
+    }
+
+    pub fn product_names(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        match self {
+            Extraction::Auftrag(o) => Box::new(o.produkte.iter().map(|p| &p.produkt)),
+            Extraction::Rechnung(r) => Box::new(r.produkte.iter().map(|p| &p.produkt)),
+            Extraction::Lieferschein(d) => Box::new(d.produkte.iter().map(|p| &p.produkt)),
+            Extraction::Gutschrift(g) => Box::new(g.produkte.iter().map(|p| &p.produkt)),
+        }
+    }
+
+    pub fn parties_mut(&mut self) -> (&mut Option<String>, &mut Option<String>) {
+        match self {
+            Extraction::Auftrag(o) => (&mut o.lieferant, &mut o.kunde),
+            Extraction::Rechnung(r) => (&mut r.lieferant, &mut r.kunde),
+            Extraction::Lieferschein(d) => (&mut d.lieferant, &mut d.kunde),
+            Extraction::Gutschrift(g) => (&mut g.lieferant, &mut g.kunde),
+        }
+    }
+
+    pub fn currencies_mut(&mut self) -> Box<dyn Iterator<Item = &mut String> + '_> {
+        match self {
+            Extraction::Auftrag(o) => {
+                Box::new(o.produkte.iter_mut().filter_map(|p| p.waehrung.as_mut()))
+            }
+            _ => Box::new(std::iter::empty()),
+        }
+    }
+
+    pub fn units_mut(&mut self) -> Box<dyn Iterator<Item = &mut String> + '_> {
+        match self {
+            Extraction::Auftrag(o) => {
+                Box::new(o.produkte.iter_mut().filter_map(|p| p.einheit.as_mut()))
+            }
+            Extraction::Rechnung(r) => {
+                Box::new(r.produkte.iter_mut().filter_map(|p| p.einheit.as_mut()))
+            }
+            Extraction::Lieferschein(d) => {
+                Box::new(d.produkte.iter_mut().filter_map(|p| p.einheit.as_mut()))
+            }
+            Extraction::Gutschrift(g) => {
+                Box::new(g.produkte.iter_mut().filter_map(|p| p.einheit.as_mut()))
+            }
+        }
+    }
+
+    pub fn products_mut(&mut self) -> Box<dyn Iterator<Item = (&mut String, Option<&str>)> + '_> {
+        match self {
+            Extraction::Auftrag(o) => Box::new(
+                o.produkte
+                    .iter_mut()
+                    .map(|p| (&mut p.produkt, p.artikelnummer.as_deref())),
+            ),
+            Extraction::Rechnung(r) => Box::new(
+                r.produkte
+                    .iter_mut()
+                    .map(|p| (&mut p.produkt, p.artikelnummer.as_deref())),
+            ),
+            Extraction::Lieferschein(d) => Box::new(
+                d.produkte
+                    .iter_mut()
+                    .map(|p| (&mut p.produkt, p.artikelnummer.as_deref())),
+            ),
+            Extraction::Gutschrift(g) => Box::new(
+                g.produkte
+                    .iter_mut()
+                    .map(|p| (&mut p.produkt, p.artikelnummer.as_deref())),
+            ),
+        }
+    }
+
+    pub fn product_names_mut(&mut self) -> Box<dyn Iterator<Item = &mut String> + '_> {
+        match self {
+            Extraction::Auftrag(o) => Box::new(o.produkte.iter_mut().map(|p| &mut p.produkt)),
+            Extraction::Rechnung(r) => Box::new(r.produkte.iter_mut().map(|p| &mut p.produkt)),
+            Extraction::Lieferschein(d) => Box::new(d.produkte.iter_mut().map(|p| &mut p.produkt)),
+            Extraction::Gutschrift(g) => Box::new(g.produkte.iter_mut().map(|p| &mut p.produkt)),
+        }
+    }
+}
+
+pub fn schema() -> Value {
+    let nullable_number = json!({ "type": ["number", "null"] });
+    let nullable_string = json!({ "type": ["string", "null"] });
+
+    json!({
+        "version": SCHEMA_VERSION,
+        "auftrag": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["produkte"],
+            "properties": {
+                "produkte": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["produkt"],
+                        "properties": {
+                            "produkt": { "type": "string" },
+                            "artikelnummer": nullable_string,
+                            "einheit": nullable_string,
+                            "menge": nullable_number,
+                            "waehrung": nullable_string,
+                            "preis": nullable_number
+                        }
+                    }
+                },
+                "nummerAuftrag": nullable_string,
+                "datumAuftrag": nullable_string,
+                "lieferant": nullable_string,
+                "kunde": nullable_string
+            }
+        },
+        "rechnung": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["produkte"],
+            "properties": {
+                "produkte": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["produkt"],
+                        "properties": {
+                            "produkt": { "type": "string" },
+                            "artikelnummer": nullable_string,
+                            "einheit": nullable_string,
+                            "gelieferteMenge": nullable_number,
+                            "preis": nullable_number,
+                            "nummerAuftrag": nullable_string
+                        }
+                    }
+                },
+                "nummerRechnung": nullable_string,
+                "datumRechnung": nullable_string,
+                "lieferant": nullable_string,
+                "kunde": nullable_string,
+                "auftraege": { "type": "array", "items": { "type": "string" } }
+            }
+        },
+        "lieferschein": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["produkte"],
+            "properties": {
+                "produkte": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["produkt"],
+                        "properties": {
+                            "produkt": { "type": "string" },
+                            "artikelnummer": nullable_string,
+                            "einheit": nullable_string,
+                            "gelieferteMenge": nullable_number,
+                            "nummerAuftrag": nullable_string
+                        }
+                    }
+                },
+                "nummerDdt": nullable_string,
+                "datumDdt": nullable_string,
+                "lieferant": nullable_string,
+                "kunde": nullable_string,
+                "auftraege": { "type": "array", "items": { "type": "string" } }
+            }
+        },
+        "gutschrift": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["produkte"],
+            "properties": {
+                "produkte": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["produkt"],
+                        "properties": {
+                            "produkt": { "type": "string" },
+                            "artikelnummer": nullable_string,
+                            "einheit": nullable_string,
+                            "gelieferteMenge": nullable_number,
+                            "preis": nullable_number,
+                            "nummerAuftrag": nullable_string
+                        }
+                    }
+                },
+                "nummerGutschrift": nullable_string,
+                "datumGutschrift": nullable_string,
+                "lieferant": nullable_string,
+                "kunde": nullable_string,
+                "auftraege": { "type": "array", "items": { "type": "string" } },
+                "rechnungen": { "type": "array", "items": { "type": "string" } }
+            }
+        }
+    })
+}
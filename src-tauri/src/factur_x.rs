@@ -0,0 +1,422 @@
+use pdf_oxide::object::Object;
+use pdf_oxide::PdfDocument;
+
+use crate::extraction::{Extraction, InvoiceExtraction, InvoiceProduct};
+use crate::xml::{self, Node};
+
+const ATTACHMENT_NAMES: [&str; 3] = ["factur-x.xml", "zugferd-invoice.xml", "xrechnung.xml"];
+const CII_ROOT: &str = "CrossIndustryInvoice";
+const UBL_INVOICE_ROOT: &str = "Invoice";
+const UBL_CREDIT_NOTE_ROOT: &str = "CreditNote";
+const MAX_NAME_TREE_DEPTH: usize = 16;
+const CREDIT_NOTE: &str = "381";
+
+#[derive(Default)]
+struct Line {
+    produkt: String,
+    artikelnummer: Option<String>,
+    menge: Option<f64>,
+    preis: Option<f64>,
+    basis: Option<f64>,
+    nummer_auftrag: Option<String>,
+}
+
+#[derive(Default)]
+struct Invoice {
+    type_code: Option<String>,
+    nummer: Option<String>,
+    datum: Option<String>,
+    nummer_auftrag: Option<String>,
+    seller: Option<String>,
+    buyer: Option<String>,
+    rechnungen: Vec<String>,
+    lines: Vec<Line>,
+}
+
+fn resolve(doc: &mut PdfDocument, obj: &Object) -> Option<Object> {
+    match obj.as_reference() {
+        Some(r) => doc.load_object(r).ok(),
+        None => Some(obj.clone()),
+    }
+}
+
+fn pdf_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn collect_file_specs(doc: &mut PdfDocument, node: &Object, specs: &mut Vec<Object>, depth: usize) {
+    if depth > MAX_NAME_TREE_DEPTH {
+        return;
+    }
+    let Some(node) = resolve(doc, node) else {
+        return;
+    };
+    let Some(dict) = node.as_dict() else {
+        return;
+    };
+
+    if let Some(names) = dict.get("Names").and_then(|n| resolve(doc, n)) {
+        if let Some(names) = names.as_array() {
+            specs.extend(names.chunks(2).filter_map(|pair| pair.get(1).cloned()));
+        }
+    }
+    if let Some(kids) = dict.get("Kids").and_then(|k| resolve(doc, k)) {
+        for kid in kids.as_array().into_iter().flatten() {
+            collect_file_specs(doc, kid, specs, depth + 1);
+        }
+    }
+}
+
+fn attachment(doc: &mut PdfDocument, spec: &Object) -> Option<(String, Vec<u8>)> {
+    let spec = resolve(doc, spec)?;
+    let dict = spec.as_dict()?;
+    let name = ["UF", "F"]
+        .iter()
+        .find_map(|key| dict.get(*key).and_then(|n| n.as_string()))
+        .map(pdf_string)?;
+
+    let embedded = resolve(doc, dict.get("EF")?)?;
+    let stream = embedded
+        .as_dict()
+        .and_then(|ef| ef.get("F").or_else(|| ef.get("UF")))
+        .cloned()?;
+    let data = resolve(doc, &stream)?.decode_stream_data().ok()?;
+    Some((name, data))
+}
+
+fn find_invoice_xml(path: &str) -> Result<Option<Vec<u8>>, String> {
+    let mut doc =
+        PdfDocument::open(path).map_err(|e| format!("Impossibile aprire il PDF: {}", e))?;
+    let catalog = doc
+        .catalog()
+        .map_err(|e| format!("Struttura PDF non valida: {}", e))?;
+    let Some(catalog) = catalog.as_dict() else {
+        return Ok(None);
+    };
+
+    let mut specs = Vec::new();
+    if let Some(af) = catalog.get("AF").and_then(|a| resolve(&mut doc, a)) {
+        specs.extend(af.as_array().into_iter().flatten().cloned());
+    }
+    if let Some(names) = catalog.get("Names").and_then(|n| resolve(&mut doc, n)) {
+        if let Some(files) = names.as_dict().and_then(|n| n.get("EmbeddedFiles")) {
+            collect_file_specs(&mut doc, files, &mut specs, 0);
+        }
+    }
+
+    for spec in &specs {
+        if let Some((name, data)) = attachment(&mut doc, spec) {
+            let name = name.trim().to_lowercase();
+            if ATTACHMENT_NAMES.contains(&name.as_str()) {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse().ok()
+}
+
+fn format_date(text: &str) -> Option<String> {
+    let digits = text.trim();
+    if digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!(
+        "{}.{}.{}",
+        &digits[6..8],
+        &digits[4..6],
+        &digits[0..4]
+    ))
+}
+
+fn format_iso_date(text: &str) -> Option<String> {
+    format_date(&text.trim().replace('-', ""))
+}
+
+fn push_unique(ids: &mut Vec<String>, id: &str) {
+    let id = id.trim();
+    if !id.is_empty() && !ids.iter().any(|r| r == id) {
+        ids.push(id.to_string());
+    }
+}
+
+fn apply_cii_text(path: &[String], invoice: &mut Invoice, text: String) {
+    let tail: Vec<&str> = path.iter().rev().take(3).map(|s| s.as_str()).collect();
+    let in_line = path.iter().any(|p| p == "IncludedSupplyChainTradeLineItem");
+
+    match tail.as_slice() {
+        ["ID", "ExchangedDocument", ..] => invoice.nummer = Some(text.trim().to_string()),
+        ["TypeCode", "ExchangedDocument", ..] => invoice.type_code = Some(text.trim().to_string()),
+        ["IssuerAssignedID", "InvoiceReferencedDocument", _] => {
+            push_unique(&mut invoice.rechnungen, &text)
+        }
+        ["DateTimeString", "IssueDateTime", "ExchangedDocument"] => {
+            invoice.datum = format_date(&text)
+        }
+        ["Name", "SellerTradeParty", _] => invoice.seller = Some(text.trim().to_string()),
+        ["Name", "BuyerTradeParty", _] => invoice.buyer = Some(text.trim().to_string()),
+        ["IssuerAssignedID", "BuyerOrderReferencedDocument", _] => {
+            let id = Some(text.trim().to_string()).filter(|id| !id.is_empty());
+            match invoice.lines.last_mut() {
+                Some(line) if in_line => line.nummer_auftrag = id,
+                _ => invoice.nummer_auftrag = id,
+            }
+        }
+        _ if in_line => {
+            let Some(line) = invoice.lines.last_mut() else {
+                return;
+            };
+            match tail.as_slice() {
+                ["Name", "SpecifiedTradeProduct", ..] => line.produkt.push_str(&text),
+                ["SellerAssignedID", "SpecifiedTradeProduct", ..] => {
+                    line.artikelnummer = Some(text.trim().to_string()).filter(|id| !id.is_empty())
+                }
+                ["BilledQuantity", ..] => line.menge = parse_number(&text),
+                ["ChargeAmount", "NetPriceProductTradePrice", ..] => {
+                    line.preis = parse_number(&text)
+                }
+                ["BasisQuantity", "NetPriceProductTradePrice", ..] => {
+                    line.basis = parse_number(&text)
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+// XRechnung in UBL syntax: same fields as CII, addressed relative to the root.
+fn apply_ubl_text(path: &[String], invoice: &mut Invoice, text: String) {
+    let rel: Vec<&str> = path.iter().skip(1).map(|s| s.as_str()).collect();
+    let value = Some(text.trim().to_string()).filter(|v| !v.is_empty());
+
+    match rel.as_slice() {
+        ["ID"] => invoice.nummer = value,
+        ["InvoiceTypeCode"] | ["CreditNoteTypeCode"] => invoice.type_code = value,
+        ["IssueDate"] => invoice.datum = format_iso_date(&text),
+        ["OrderReference", "ID"] => invoice.nummer_auftrag = value,
+        ["BillingReference", "InvoiceDocumentReference", "ID"] => {
+            push_unique(&mut invoice.rechnungen, &text)
+        }
+        ["AccountingSupplierParty", "Party", "PartyLegalEntity", "RegistrationName"] => {
+            invoice.seller = value
+        }
+        ["AccountingSupplierParty", "Party", "PartyName", "Name"] => {
+            invoice.seller = invoice.seller.take().or(value)
+        }
+        ["AccountingCustomerParty", "Party", "PartyLegalEntity", "RegistrationName"] => {
+            invoice.buyer = value
+        }
+        ["AccountingCustomerParty", "Party", "PartyName", "Name"] => {
+            invoice.buyer = invoice.buyer.take().or(value)
+        }
+        ["InvoiceLine" | "CreditNoteLine", rest @ ..] => {
+            let Some(line) = invoice.lines.last_mut() else {
+                return;
+            };
+            match rest {
+                ["InvoicedQuantity" | "CreditedQuantity"] => line.menge = parse_number(&text),
+                ["Item", "Name"] => line.produkt.push_str(&text),
+                ["Item", "SellersItemIdentification", "ID"] => line.artikelnummer = value,
+                ["Price", "PriceAmount"] => line.preis = parse_number(&text),
+                ["Price", "BaseQuantity"] => line.basis = parse_number(&text),
+                ["OrderLineReference", "OrderReference", "ID"] => line.nummer_auftrag = value,
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_line_start(path: &[String]) -> bool {
+    match path.first().map(|s| s.as_str()) {
+        Some(CII_ROOT) => {
+            path.last().map(|s| s.as_str()) == Some("IncludedSupplyChainTradeLineItem")
+        }
+        Some(_) => path.len() == 2 && matches!(path[1].as_str(), "InvoiceLine" | "CreditNoteLine"),
+        None => false,
+    }
+}
+
+fn parse_invoice(bytes: Vec<u8>) -> Result<Extraction, String> {
+    let content = xml::decode(bytes);
+    let mut invoice = Invoice::default();
+
+    let root = xml::walk(&content, |node| match node {
+        Node::Start(path) => {
+            if is_line_start(path) {
+                invoice.lines.push(Line::default());
+            }
+        }
+        Node::Text(path, text) => match path.first().map(|s| s.as_str()) {
+            Some(CII_ROOT) => apply_cii_text(path, &mut invoice, text),
+            Some(UBL_INVOICE_ROOT | UBL_CREDIT_NOTE_ROOT) => {
+                apply_ubl_text(path, &mut invoice, text)
+            }
+            _ => {}
+        },
+    })
+    .map_err(|e| format!("XML Factur-X non leggibile: {}", e))?;
+
+    let root = root.unwrap_or_default();
+    if ![CII_ROOT, UBL_INVOICE_ROOT, UBL_CREDIT_NOTE_ROOT].contains(&root.as_str()) {
+        return Err(format!(
+            "L'allegato XML ({}) non è una fattura CII (Factur-X/ZUGFeRD) né UBL (XRechnung).",
+            root
+        ));
+    }
+
+    let mut auftraege: Vec<String> = Vec::new();
+    let references = invoice.nummer_auftrag.iter();
+    for id in references.chain(
+        invoice
+            .lines
+            .iter()
+            .filter_map(|l| l.nummer_auftrag.as_ref()),
+    ) {
+        if !auftraege.contains(id) {
+            auftraege.push(id.clone());
+        }
+    }
+
+    let produkte = invoice
+        .lines
+        .into_iter()
+        .filter(|l| !l.produkt.trim().is_empty())
+        .map(|l| InvoiceProduct {
+            produkt: l.produkt.trim().to_string(),
+            artikelnummer: l.artikelnummer,
+            einheit: None,
+            gelieferte_menge: l.menge,
+            preis: match (l.preis, l.basis) {
+                (Some(preis), Some(basis)) if basis > 0.0 => Some(preis / basis),
+                (preis, _) => preis,
+            },
+            nummer_auftrag: l.nummer_auftrag.or_else(|| invoice.nummer_auftrag.clone()),
+        })
+        .collect();
+
+    let extraction = InvoiceExtraction {
+        produkte,
+        nummer_rechnung: invoice.nummer,
+        datum_rechnung: invoice.datum,
+        lieferant: invoice.seller.filter(|n| !n.is_empty()),
+        kunde: invoice.buyer.filter(|n| !n.is_empty()),
+        auftraege,
+    };
+
+    let credit_note =
+        root == UBL_CREDIT_NOTE_ROOT || invoice.type_code.as_deref() == Some(CREDIT_NOTE);
+    Ok(if credit_note {
+        Extraction::Gutschrift(extraction.into_credit_note(invoice.rechnungen))
+    } else {
+        Extraction::Rechnung(extraction)
+    })
+}
+
+pub fn extract(path: &str) -> Result<Option<Extraction>, String> {
+    match find_invoice_xml(path)? {
+        Some(bytes) => parse_invoice(bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CII: &str = include_str!("../tests/fixtures/factur_x_cii.xml");
+    const UBL: &str = include_str!("../tests/fixtures/xrechnung_ubl.xml");
+
+    #[test]
+    fn cii_lines_are_mapped_with_unit_prices_and_orders() {
+        let Extraction::Rechnung(invoice) = parse_invoice(CII.into()).unwrap() else {
+            panic!("expected an invoice");
+        };
+        assert_eq!(invoice.nummer_rechnung.as_deref(), Some("RE-2026-0457"));
+        assert_eq!(invoice.datum_rechnung.as_deref(), Some("12.10.2026"));
+        assert_eq!(invoice.lieferant.as_deref(), Some("Elektro Huber GmbH"));
+        assert_eq!(invoice.kunde.as_deref(), Some("Bauhof Meier KG"));
+        assert_eq!(invoice.auftraege, ["A-1002", "A-1001"]);
+
+        assert_eq!(invoice.produkte.len(), 2);
+        let klemme = &invoice.produkte[0];
+        assert_eq!(klemme.produkt, "Schraubklemme 4 mm²");
+        assert_eq!(klemme.artikelnummer.as_deref(), Some("SK-2040"));
+        assert_eq!(klemme.gelieferte_menge, Some(50.0));
+        assert_eq!(klemme.preis, Some(1.25));
+        assert_eq!(klemme.nummer_auftrag.as_deref(), Some("A-1001"));
+        let kanal = &invoice.produkte[1];
+        assert_eq!(kanal.artikelnummer, None);
+        assert_eq!(kanal.preis, Some(8.9));
+        assert_eq!(kanal.nummer_auftrag.as_deref(), Some("A-1002"));
+    }
+
+    #[test]
+    fn cii_type_code_381_is_a_credit_note() {
+        let xml = CII
+            .replace("<ram:TypeCode>380", "<ram:TypeCode>381")
+            .replace(
+                "<ram:ApplicableHeaderTradeSettlement>",
+                "<ram:ApplicableHeaderTradeSettlement><ram:InvoiceReferencedDocument><ram:IssuerAssignedID>RE-2026-0400</ram:IssuerAssignedID></ram:InvoiceReferencedDocument>",
+            );
+        let Extraction::Gutschrift(credit) = parse_invoice(xml.into_bytes()).unwrap() else {
+            panic!("expected a credit note");
+        };
+        assert_eq!(credit.nummer_gutschrift.as_deref(), Some("RE-2026-0457"));
+        assert_eq!(credit.rechnungen, ["RE-2026-0400"]);
+    }
+
+    #[test]
+    fn xrechnung_ubl_credit_note_is_mapped() {
+        let Extraction::Gutschrift(credit) = parse_invoice(UBL.into()).unwrap() else {
+            panic!("expected a credit note");
+        };
+        assert_eq!(credit.nummer_gutschrift.as_deref(), Some("GS-2026-031"));
+        assert_eq!(credit.datum_gutschrift.as_deref(), Some("14.10.2026"));
+        assert_eq!(credit.lieferant.as_deref(), Some("Elektro Huber GmbH"));
+        assert_eq!(credit.kunde.as_deref(), Some("Bauhof Meier KG"));
+        assert_eq!(credit.rechnungen, ["RE-2026-0457"]);
+        assert_eq!(credit.auftraege, ["A-1002", "A-1001"]);
+
+        let [kanal] = credit.produkte.as_slice() else {
+            panic!("expected one line");
+        };
+        assert_eq!(kanal.produkt, "Kabelkanal 40x60");
+        assert_eq!(kanal.artikelnummer.as_deref(), Some("KK-4060"));
+        assert_eq!(kanal.gelieferte_menge, Some(5.0));
+        assert_eq!(kanal.preis, Some(8.9));
+        assert_eq!(kanal.nummer_auftrag.as_deref(), Some("A-1001"));
+    }
+
+    #[test]
+    fn ubl_invoice_root_is_an_invoice() {
+        let xml = UBL
+            .replace("CreditNote", "Invoice")
+            .replace("InvoiceTypeCode>381", "InvoiceTypeCode>380")
+            .replace("CreditedQuantity", "InvoicedQuantity");
+        let Extraction::Rechnung(invoice) = parse_invoice(xml.into_bytes()).unwrap() else {
+            panic!("expected an invoice");
+        };
+        assert_eq!(invoice.produkte[0].gelieferte_menge, Some(5.0));
+    }
+
+    #[test]
+    fn other_xml_roots_are_rejected_by_name() {
+        let error = parse_invoice(b"<Order><ID>1</ID></Order>".to_vec()).unwrap_err();
+        assert!(error.contains("(Order)"), "{}", error);
+    }
+}
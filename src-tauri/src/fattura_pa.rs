@@ -0,0 +1,546 @@
+use base64::Engine;
+use std::fs;
+use std::path::Path;
+
+use crate::extraction::{Extraction, InvoiceExtraction, InvoiceProduct};
+use crate::xml::{self, Node};
+
+const ROOT_ELEMENT: &str = "FatturaElettronica";
+const CREDIT_NOTE: &str = "TD04";
+
+#[derive(Default)]
+struct Line {
+    numero: Option<u32>,
+    descrizione: String,
+    codice: Option<String>,
+    unita: Option<String>,
+    quantita: Option<f64>,
+    prezzo: Option<f64>,
+    tipo: Option<String>,
+}
+
+#[derive(Default)]
+struct OrderRef {
+    id: String,
+    lines: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Parties {
+    cedente: String,
+    cessionario: String,
+}
+
+#[derive(Default)]
+struct Body {
+    tipo: Option<String>,
+    numero: Option<String>,
+    data: Option<String>,
+    orders: Vec<OrderRef>,
+    invoices: Vec<String>,
+    lines: Vec<Line>,
+}
+
+fn is_signed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("p7m"))
+}
+
+pub fn has_document_extension(path: &Path) -> bool {
+    is_signed(path)
+        || path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("xml"))
+}
+
+// Decides by file name only, for callers that may not be able to read the file.
+pub fn has_xml_name(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".xml") || (name.ends_with(".p7m") && !name.ends_with(".pdf.p7m"))
+}
+
+// Only files whose root element is FatturaElettronica take the XML path; other XML files and
+// signed PDFs go through the PDF analysis.
+pub fn is_candidate(path: &Path) -> bool {
+    has_document_extension(path)
+        && read_document(path)
+            .ok()
+            .and_then(|content| xml::walk(&content, |_| {}).ok().flatten())
+            .is_some_and(|root| root == ROOT_ELEMENT)
+}
+
+pub fn signed_pdf(path: &Path) -> Option<Vec<u8>> {
+    if !is_signed(path) {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    signed_octets(&bytes)
+        .ok()?
+        .into_iter()
+        .find(|o| o.starts_with(b"%PDF"))
+}
+
+pub fn document_stem(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let lower = name.to_lowercase();
+    for suffix in [".xml.p7m", ".p7m", ".xml", ".pdf"] {
+        if lower.ends_with(suffix) {
+            return name[..name.len() - suffix.len()].to_string();
+        }
+    }
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn der_header(data: &[u8]) -> Option<(u8, usize, Option<usize>)> {
+    let tag = *data.first()?;
+    if tag & 0x1f == 0x1f {
+        return None;
+    }
+    let first = *data.get(1)? as usize;
+    match first {
+        0..=0x7f => Some((tag, 2, Some(first))),
+        0x80 => Some((tag, 2, None)),
+        _ => {
+            let n = first & 0x7f;
+            if n > 4 {
+                return None;
+            }
+            let len = data
+                .get(2..2 + n)?
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            Some((tag, 2 + n, Some(len)))
+        }
+    }
+}
+
+fn der_octets(data: &[u8], octets: &mut Vec<Vec<u8>>) -> Option<usize> {
+    let (tag, header, len) = der_header(data)?;
+
+    if tag & 0x20 == 0 {
+        let len = len?;
+        if tag == 0x04 {
+            octets.push(data.get(header..header + len)?.to_vec());
+        }
+        return Some(header + len);
+    }
+
+    let mut children = Vec::new();
+    let mut pos = header;
+    loop {
+        match len {
+            Some(len) if pos >= header + len => break,
+            None if data.get(pos..pos + 2) == Some(&[0, 0]) => {
+                pos += 2;
+                break;
+            }
+            _ => pos += der_octets(data.get(pos..)?, &mut children)?,
+        }
+    }
+
+    if tag == 0x24 {
+        octets.push(children.concat());
+    } else {
+        octets.extend(children);
+    }
+    Some(pos)
+}
+
+fn signed_octets(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let der = if bytes.first() == Some(&0x30) {
+        bytes.to_vec()
+    } else {
+        let text: String = String::from_utf8_lossy(bytes)
+            .lines()
+            .filter(|l| !l.starts_with("-----"))
+            .collect::<String>()
+            .split_whitespace()
+            .collect();
+        base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(|_| "File .p7m non riconosciuto.".to_string())?
+    };
+
+    let mut octets = Vec::new();
+    der_octets(&der, &mut octets).ok_or("Busta di firma .p7m non valida.".to_string())?;
+    Ok(octets)
+}
+
+fn signed_payload(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    signed_octets(bytes)?
+        .into_iter()
+        .find(|o| contains(o, ROOT_ELEMENT.as_bytes()))
+        .ok_or("Nessuna fattura elettronica nella busta .p7m.".to_string())
+}
+
+fn read_document(path: &Path) -> Result<String, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Impossibile leggere il file: {}", e))?;
+    Ok(xml::decode(if is_signed(path) {
+        signed_payload(&bytes)?
+    } else {
+        bytes
+    }))
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse().ok()
+}
+
+fn format_date(text: &str) -> Option<String> {
+    let mut parts = text.trim().splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    Some(format!("{}.{}.{}", day, month, year))
+}
+
+fn apply_text(path: &[String], body: &mut Body, text: String) {
+    let (Some(leaf), Some(parent)) = (path.last(), path.iter().rev().nth(1)) else {
+        return;
+    };
+
+    match (parent.as_str(), leaf.as_str()) {
+        ("DatiGeneraliDocumento", "TipoDocumento") => body.tipo = Some(text.trim().to_string()),
+        ("DatiGeneraliDocumento", "Numero") => body.numero = Some(text),
+        ("DatiFattureCollegate", "IdDocumento") => {
+            let id = text.trim();
+            if !id.is_empty() && !body.invoices.iter().any(|i| i == id) {
+                body.invoices.push(id.to_string());
+            }
+        }
+        ("DatiGeneraliDocumento", "Data") => body.data = format_date(&text),
+        ("DatiOrdineAcquisto", field) => {
+            if let Some(order) = body.orders.last_mut() {
+                match field {
+                    "IdDocumento" => order.id = text,
+                    "RiferimentoNumeroLinea" => order.lines.extend(text.trim().parse::<u32>().ok()),
+                    _ => {}
+                }
+            }
+        }
+        ("CodiceArticolo", "CodiceValore") => {
+            if let Some(line) = body.lines.last_mut() {
+                let code = text.trim();
+                if line.codice.is_none() && !code.is_empty() {
+                    line.codice = Some(code.to_string());
+                }
+            }
+        }
+        ("DettaglioLinee", field) => {
+            if let Some(line) = body.lines.last_mut() {
+                match field {
+                    "NumeroLinea" => line.numero = text.trim().parse().ok(),
+                    "Descrizione" => line.descrizione.push_str(&text),
+                    "Quantita" => line.quantita = parse_number(&text),
+                    "UnitaMisura" => {
+                        line.unita = Some(text.trim().to_string()).filter(|u| !u.is_empty())
+                    }
+                    "PrezzoUnitario" => line.prezzo = parse_number(&text),
+                    "TipoCessionePrestazione" => line.tipo = Some(text),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn apply_party(path: &[String], parties: &mut Parties, text: String) {
+    let name = match path.get(1).map(|s| s.as_str()) {
+        Some("CedentePrestatore") => &mut parties.cedente,
+        Some("CessionarioCommittente") => &mut parties.cessionario,
+        _ => return,
+    };
+    let in_anagrafica = path.iter().rev().nth(1).is_some_and(|p| p == "Anagrafica");
+    if in_anagrafica
+        && ["Denominazione", "Nome", "Cognome"].contains(&path[path.len() - 1].as_str())
+    {
+        if !name.is_empty() {
+            name.push(' ');
+        }
+        name.push_str(text.trim());
+    }
+}
+
+fn parse_bodies(xml: &str) -> Result<(Parties, Vec<Body>), String> {
+    let mut parties = Parties::default();
+    let mut bodies: Vec<Body> = Vec::new();
+
+    let root = xml::walk(xml, |node| match node {
+        Node::Start(path) => match path.last().map(|s| s.as_str()) {
+            Some("FatturaElettronicaBody") => bodies.push(Body::default()),
+            Some("DatiOrdineAcquisto") => {
+                if let Some(body) = bodies.last_mut() {
+                    body.orders.push(OrderRef::default());
+                }
+            }
+            Some("DettaglioLinee") => {
+                if let Some(body) = bodies.last_mut() {
+                    body.lines.push(Line::default());
+                }
+            }
+            _ => {}
+        },
+        Node::Text(path, text) => {
+            if path.get(1).is_some_and(|p| p == "FatturaElettronicaHeader") {
+                apply_party(&path[1..], &mut parties, text);
+            } else if let Some(body) = bodies.last_mut() {
+                apply_text(path, body, text);
+            }
+        }
+    })
+    .map_err(|e| format!("Fattura elettronica non leggibile: {}", e))?;
+
+    if root.as_deref() != Some(ROOT_ELEMENT) {
+        return Err("Il file XML non è una fattura elettronica FatturaPA.".to_string());
+    }
+    Ok((parties, bodies))
+}
+
+fn to_extraction(body: Body, parties: Parties) -> Extraction {
+    let document_order = body
+        .orders
+        .iter()
+        .find(|o| o.lines.is_empty() && !o.id.trim().is_empty())
+        .map(|o| o.id.trim().to_string());
+
+    let produkte = body
+        .lines
+        .into_iter()
+        .filter(|l| l.tipo.is_none() && !l.descrizione.trim().is_empty())
+        .filter(|l| l.quantita.is_some() || l.prezzo.is_some_and(|p| p != 0.0))
+        .map(|l| {
+            let nummer_auftrag = body
+                .orders
+                .iter()
+                .find(|o| l.numero.is_some_and(|n| o.lines.contains(&n)))
+                .map(|o| o.id.trim().to_string())
+                .filter(|id| !id.is_empty())
+                .or_else(|| document_order.clone());
+
+            InvoiceProduct {
+                produkt: l.descrizione.trim().to_string(),
+                artikelnummer: l.codice,
+                einheit: l.unita,
+                gelieferte_menge: l.quantita,
+                preis: l.prezzo,
+                nummer_auftrag,
+            }
+        })
+        .collect();
+
+    let mut auftraege: Vec<String> = Vec::new();
+    for order in &body.orders {
+        let id = order.id.trim();
+        if !id.is_empty() && !auftraege.iter().any(|a| a == id) {
+            auftraege.push(id.to_string());
+        }
+    }
+
+    let invoice = InvoiceExtraction {
+        produkte,
+        nummer_rechnung: body.numero.map(|n| n.trim().to_string()),
+        datum_rechnung: body.data,
+        lieferant: Some(parties.cedente).filter(|n| !n.is_empty()),
+        kunde: Some(parties.cessionario).filter(|n| !n.is_empty()),
+        auftraege,
+    };
+
+    if body.tipo.as_deref() == Some(CREDIT_NOTE) {
+        Extraction::Gutschrift(invoice.into_credit_note(body.invoices))
+    } else {
+        Extraction::Rechnung(invoice)
+    }
+}
+
+fn describe(body: &Body) -> String {
+    format!(
+        "n. {} del {} ({} righe)",
+        body.numero.as_deref().map(str::trim).unwrap_or("-"),
+        body.data.as_deref().unwrap_or("-"),
+        body.lines.len()
+    )
+}
+
+fn parse(content: &str) -> Result<Extraction, String> {
+    let (parties, mut bodies) = parse_bodies(content)?;
+    match bodies.len() {
+        0 => Err("La fattura elettronica non contiene alcun documento.".to_string()),
+        1 => Ok(to_extraction(bodies.remove(0), parties)),
+        n => Err(format!(
+            "Il file è un lotto di {} fatture ({}); i lotti non sono supportati, importare le fatture singolarmente.",
+            n,
+            bodies.iter().map(describe).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+pub fn parse_file(path: &Path) -> Result<Extraction, String> {
+    parse(&read_document(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FATTURA: &str = include_str!("../tests/fixtures/fattura_pa.xml");
+    const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+    const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            n @ 0..=0x7f => out.push(n as u8),
+            n @ 0x80..=0xff => out.extend([0x81, n as u8]),
+            n => out.extend([0x82, (n >> 8) as u8, n as u8]),
+        }
+        out.extend(content);
+        out
+    }
+
+    // PKCS#7 SignedData as written by Italian signing tools: the document sits in
+    // encapContentInfo, the signature is a second OCTET STRING in the signer info.
+    fn envelope(content: Vec<u8>) -> Vec<u8> {
+        let encap = der(0x30, &[der(0x06, OID_DATA), content].concat());
+        let signer = der(0x31, &der(0x30, &der(0x04, &[0xde, 0xad, 0xbe, 0xef])));
+        let signed_data = der(
+            0x30,
+            &[der(0x02, &[1]), der(0x31, &[]), encap, signer].concat(),
+        );
+        der(
+            0x30,
+            &[der(0x06, OID_SIGNED_DATA), der(0xa0, &signed_data)].concat(),
+        )
+    }
+
+    fn temp_file(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("maggus-fpa-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn signed_payload_is_taken_from_the_encapsulated_content() {
+        let p7m = envelope(der(0xa0, &der(0x04, FATTURA.as_bytes())));
+        assert_eq!(signed_payload(&p7m).unwrap(), FATTURA.as_bytes());
+    }
+
+    #[test]
+    fn chunked_indefinite_length_payload_is_reassembled() {
+        let (head, tail) = FATTURA.as_bytes().split_at(1000);
+        let content = [
+            &[0xa0, 0x80, 0x24, 0x80][..],
+            &der(0x04, head),
+            &der(0x04, tail),
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(
+            signed_payload(&envelope(content)).unwrap(),
+            FATTURA.as_bytes()
+        );
+    }
+
+    #[test]
+    fn base64_encoded_envelope_is_decoded() {
+        let p7m = envelope(der(0xa0, &der(0x04, FATTURA.as_bytes())));
+        let encoded = base64::engine::general_purpose::STANDARD.encode(p7m);
+        let pem = format!("-----BEGIN PKCS7-----\n{}\n-----END PKCS7-----\n", encoded);
+        assert_eq!(signed_payload(pem.as_bytes()).unwrap(), FATTURA.as_bytes());
+        assert!(signed_payload(b"not a signature").is_err());
+    }
+
+    #[test]
+    fn invoice_lines_are_mapped_to_orders_and_codes() {
+        let Extraction::Rechnung(invoice) = parse(FATTURA).unwrap() else {
+            panic!("expected an invoice");
+        };
+        assert_eq!(invoice.nummer_rechnung.as_deref(), Some("FT/2026/118"));
+        assert_eq!(invoice.datum_rechnung.as_deref(), Some("05.10.2026"));
+        assert_eq!(
+            invoice.lieferant.as_deref(),
+            Some("Ferramenta Rossi S.r.l.")
+        );
+        assert_eq!(invoice.kunde.as_deref(), Some("Anna Bianchi"));
+        assert_eq!(invoice.auftraege, ["ORD-7781", "ORD-7790"]);
+
+        // The discount line (TipoCessionePrestazione SC) is not a product.
+        assert_eq!(invoice.produkte.len(), 2);
+        let vite = &invoice.produkte[0];
+        assert_eq!(vite.produkt, "Vite M8 zincata");
+        assert_eq!(vite.artikelnummer.as_deref(), Some("VM8-100"));
+        assert_eq!(vite.einheit.as_deref(), Some("PZ"));
+        assert_eq!(vite.gelieferte_menge, Some(100.0));
+        assert_eq!(vite.preis, Some(0.45));
+        assert_eq!(vite.nummer_auftrag.as_deref(), Some("ORD-7781"));
+        let tassello = &invoice.produkte[1];
+        assert_eq!(tassello.artikelnummer, None);
+        assert_eq!(tassello.einheit, None);
+        assert_eq!(tassello.nummer_auftrag.as_deref(), Some("ORD-7790"));
+    }
+
+    #[test]
+    fn td04_becomes_a_credit_note_with_linked_invoices() {
+        let xml = FATTURA.replace("TD01", "TD04").replace(
+            "</DatiGenerali>",
+            "<DatiFattureCollegate><IdDocumento>FT/2026/100</IdDocumento></DatiFattureCollegate></DatiGenerali>",
+        );
+        let Extraction::Gutschrift(credit) = parse(&xml).unwrap() else {
+            panic!("expected a credit note");
+        };
+        assert_eq!(credit.nummer_gutschrift.as_deref(), Some("FT/2026/118"));
+        assert_eq!(credit.rechnungen, ["FT/2026/100"]);
+    }
+
+    #[test]
+    fn batch_files_name_every_invoice_in_the_error() {
+        let start = FATTURA.find("<FatturaElettronicaBody>").unwrap();
+        let end = FATTURA.find("</p:FatturaElettronica>").unwrap();
+        let second = FATTURA[start..end].replace("FT/2026/118", "FT/2026/119");
+        let xml = format!("{}{}{}", &FATTURA[..end], second, &FATTURA[end..]);
+
+        let error = parse(&xml).unwrap_err();
+        assert!(error.contains("lotto di 2 fatture"), "{}", error);
+        assert!(error.contains("n. FT/2026/118 del 05.10.2026 (3 righe)"));
+        assert!(error.contains("n. FT/2026/119"));
+    }
+
+    #[test]
+    fn only_fattura_pa_content_takes_the_xml_path() {
+        let signed = envelope(der(0xa0, &der(0x04, FATTURA.as_bytes())));
+        assert!(is_candidate(&temp_file(
+            "IT01234567890_00042.xml",
+            FATTURA.as_bytes()
+        )));
+        assert!(is_candidate(&temp_file(
+            "IT01234567890_00042.xml.p7m",
+            &signed
+        )));
+        assert!(!is_candidate(&temp_file(
+            "ordine.xml",
+            b"<?xml version=\"1.0\"?><Order><Id>1</Id></Order>"
+        )));
+
+        let pdf = b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let signed_pdf_path = temp_file("ordine.pdf.p7m", &envelope(der(0xa0, &der(0x04, &pdf))));
+        assert!(!is_candidate(&signed_pdf_path));
+        assert_eq!(signed_pdf(&signed_pdf_path), Some(pdf));
+        assert!(!has_xml_name(&signed_pdf_path));
+        assert!(has_xml_name(Path::new("IT01234567890_00042.xml.p7m")));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::extraction::Extraction;
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMetadata {
+    pub pdf_name: String,
+    pub doc_type: String,
+    pub warnings: bool,
+    pub kunde: Option<String>,
+    pub lieferant: Option<String>,
+    pub datum_auftrag: Option<String>,
+    pub nummer_auftrag: Option<String>,
+    pub datum_rechnung: Option<String>,
+    pub datum_ddt: Option<String>,
+}
+
+fn normalize_id(value: &str) -> String {
+    let id: String = value
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_uppercase();
+    id.trim_start_matches('0').to_string()
+}
+
+fn normalize_name(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+fn normalize_date(value: &str) -> Option<(u32, u32, u32)> {
+    let parts: Vec<&str> = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|p| !p.is_empty())
+        .collect();
+    let (day, month, year) = match parts.as_slice() {
+        [digits] if digits.len() == 8 => (&digits[6..8], &digits[4..6], &digits[0..4]),
+        [year, month, day] if year.len() == 4 => (*day, *month, *year),
+        [day, month, year] => (*day, *month, *year),
+        _ => return None,
+    };
+    let year: u32 = year.parse().ok()?;
+    Some((
+        day.parse().ok()?,
+        month.parse().ok()?,
+        if year < 100 { year + 2000 } else { year },
+    ))
+}
+
+#[derive(Default)]
+struct Check {
+    mismatches: Vec<String>,
+}
+
+impl Check {
+    fn compare(
+        &mut self,
+        label: &str,
+        file: &Option<String>,
+        document: &Option<String>,
+        same: impl Fn(&str, &str) -> bool,
+    ) {
+        if let (Some(file), Some(document)) = (file, document) {
+            if !document.trim().is_empty() && !same(file, document) {
+                self.mismatches.push(format!(
+                    "{}: nome file «{}», documento «{}»",
+                    label,
+                    file,
+                    document.trim()
+                ));
+            }
+        }
+    }
+
+    fn ids(&mut self, label: &str, file: &Option<String>, document: &Option<String>) {
+        self.compare(label, file, document, |a, b| {
+            b.split(',').any(|id| normalize_id(a) == normalize_id(id))
+        });
+    }
+
+    fn date(&mut self, label: &str, file: &Option<String>, document: &Option<String>) {
+        self.compare(label, file, document, |a, b| {
+            match (normalize_date(a), normalize_date(b)) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        });
+    }
+
+    fn names(&mut self, label: &str, file: &Option<String>, document: &Option<String>) {
+        self.compare(label, file, document, |a, b| {
+            let (a, b) = (normalize_name(a), normalize_name(b));
+            a.is_empty() || b.is_empty() || a.contains(&b) || b.contains(&a)
+        });
+    }
+}
+
+pub fn cross_check(meta: &FileMetadata, extraction: &Extraction) -> Vec<String> {
+    let mut check = Check::default();
+
+    match extraction {
+        Extraction::Auftrag(order) => {
+            check.ids("Numero ordine", &meta.nummer_auftrag, &order.nummer_auftrag);
+            check.date("Data ordine", &meta.datum_auftrag, &order.datum_auftrag);
+            check.names("Fornitore", &meta.lieferant, &order.lieferant);
+            check.names("Cliente", &meta.kunde, &order.kunde);
+        }
+        Extraction::Rechnung(invoice) => {
+            check.date(
+                "Data fattura",
+                &meta.datum_rechnung,
+                &invoice.datum_rechnung,
+            );
+            check.names("Fornitore", &meta.lieferant, &invoice.lieferant);
+            check.names("Cliente", &meta.kunde, &invoice.kunde);
+            let lines = invoice.produkte.iter().map(|p| &p.nummer_auftrag);
+            check.ids(
+                "Numero ordine",
+                &meta.nummer_auftrag,
+                &referenced_orders(&invoice.auftraege, lines),
+            );
+        }
+        Extraction::Gutschrift(credit) => {
+            check.date(
+                "Data nota di credito",
+                &meta.datum_rechnung,
+                &credit.datum_gutschrift,
+            );
+            check.names("Fornitore", &meta.lieferant, &credit.lieferant);
+            check.names("Cliente", &meta.kunde, &credit.kunde);
+            let lines = credit.produkte.iter().map(|p| &p.nummer_auftrag);
+            check.ids(
+                "Numero ordine",
+                &meta.nummer_auftrag,
+                &referenced_orders(&credit.auftraege, lines),
+            );
+        }
+        Extraction::Lieferschein(note) => {
+            check.date("Data DDT", &meta.datum_ddt, &note.datum_ddt);
+            check.names("Fornitore", &meta.lieferant, &note.lieferant);
+            check.names("Cliente", &meta.kunde, &note.kunde);
+            let lines = note.produkte.iter().map(|p| &p.nummer_auftrag);
+            check.ids(
+                "Numero ordine",
+                &meta.nummer_auftrag,
+                &referenced_orders(&note.auftraege, lines),
+            );
+        }
+    }
+
+    check.mismatches
+}
+
+fn referenced_orders<'a>(
+    auftraege: &'a [String],
+    lines: impl Iterator<Item = &'a Option<String>>,
+) -> Option<String> {
+    let mut referenced: Vec<&str> = Vec::new();
+    for id in auftraege.iter().chain(lines.flatten()) {
+        if !id.trim().is_empty() && !referenced.contains(&id.trim()) {
+            referenced.push(id.trim());
+        }
+    }
+    Some(referenced.join(", ")).filter(|r| !r.is_empty())
+}
@@ -0,0 +1,402 @@
+use chrono::NaiveDate;
+use regex::{Captures, Regex};
+use serde_json::json;
+use std::path::Path;
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use crate::fattura_pa;
+use crate::filename::FileMetadata;
+use crate::settings::Settings;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d";
+
+const DOC_TYPES: [&str; 4] = ["auftrag", "rechnung", "lieferschein", "gutschrift"];
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FilenamePattern {
+    pub name: String,
+    pub doc_type: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub date_format: Option<String>,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+impl FilenamePattern {
+    fn new(name: &str, doc_type: &str, pattern: &str, extensions: &[&str]) -> FilenamePattern {
+        FilenamePattern {
+            name: name.to_string(),
+            doc_type: doc_type.to_string(),
+            pattern: pattern.to_string(),
+            date_format: None,
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternTest {
+    pub sample: String,
+    pub pattern: Option<String>,
+    pub metadata: FileMetadata,
+}
+
+struct CompiledPattern {
+    pattern: FilenamePattern,
+    regex: Regex,
+}
+
+pub struct FilenameSchema {
+    patterns: Vec<CompiledPattern>,
+}
+
+pub fn default_patterns() -> Vec<FilenamePattern> {
+    vec![
+        FilenamePattern::new(
+            "Fattura",
+            "rechnung",
+            r"^FT[^_]*(?:_(?P<lieferant>[^_]*)(?:_(?P<datumRechnung>[^_-]*)(?:-(?P<kunde>[^_-]*))?[^_]*)?(?:_(?P<nummerAuftrag>[^_]*))?)?",
+            &[],
+        ),
+        FilenamePattern::new(
+            "Nota di credito",
+            "gutschrift",
+            r"^NC[^_]*_(?P<lieferant>[^_]*)(?:_(?P<datumRechnung>[^_-]*)(?:-(?P<kunde>[^_-]*))?[^_]*)?(?:_(?P<nummerAuftrag>[^_]*))?",
+            &[],
+        ),
+        FilenamePattern::new(
+            "DDT",
+            "lieferschein",
+            r"^DDT[^_]*_(?P<lieferant>[^_]*)(?:_(?P<datumDdt>[^_-]*)(?:-(?P<kunde>[^_-]*))?[^_]*)?(?:_(?P<nummerAuftrag>[^_]*))?",
+            &[],
+        ),
+        // SDI names (IT01234567890_00042.xml) hold the sender id and a progressive
+        // number only; the parties come from the XML itself.
+        FilenamePattern::new(
+            "Fattura elettronica",
+            "rechnung",
+            r"^.+$",
+            &["xml", "xml.p7m"],
+        ),
+        FilenamePattern::new(
+            "Ordine",
+            "auftrag",
+            r"^(?P<nummerAuftrag>[^_]*)(?:_(?P<datumAuftrag>[^_]*))?(?:_(?P<lieferant>[^_-]*)(?:-(?P<kunde>[^_-]*))?)?",
+            &[],
+        ),
+    ]
+}
+
+fn date_field(caps: &Captures, group: &str, format: &str) -> Option<String> {
+    let value = caps.name(group)?.as_str();
+    NaiveDate::parse_from_str(value, format)
+        .ok()
+        .map(|d| d.format("%d.%m.%Y").to_string())
+}
+
+fn text_field(caps: &Captures, group: &str) -> Option<String> {
+    caps.name(group)
+        .map(|m| m.as_str().trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+impl FilenameSchema {
+    pub fn new(patterns: Vec<FilenamePattern>) -> Result<FilenameSchema, String> {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| {
+                if !DOC_TYPES.contains(&pattern.doc_type.as_str()) {
+                    return Err(format!(
+                        "Schema «{}»: tipo documento sconosciuto «{}»",
+                        pattern.name, pattern.doc_type
+                    ));
+                }
+                let regex = Regex::new(&pattern.pattern)
+                    .map_err(|e| format!("Schema «{}» non valido: {}", pattern.name, e))?;
+                Ok(CompiledPattern { pattern, regex })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(FilenameSchema { patterns })
+    }
+
+    pub fn load(settings: &Settings) -> FilenameSchema {
+        FilenameSchema::new(load_patterns(settings)).unwrap_or_else(|e| {
+            println!("Schemi nomi file ignorati: {}", e);
+            FilenameSchema::default()
+        })
+    }
+
+    fn find<'a>(&self, path: &Path, stem: &'a str) -> Option<(&CompiledPattern, Captures<'a>)> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        self.patterns
+            .iter()
+            .filter(|p| {
+                p.pattern.extensions.is_empty()
+                    || p.pattern
+                        .extensions
+                        .iter()
+                        .any(|ext| name.ends_with(&format!(".{}", ext.to_lowercase())))
+            })
+            .find_map(|p| p.regex.captures(stem).map(|caps| (p, caps)))
+    }
+
+    pub fn test(&self, sample: &str) -> PatternTest {
+        let path = Path::new(sample);
+        let stem = fattura_pa::document_stem(path).to_uppercase();
+        PatternTest {
+            sample: sample.to_string(),
+            pattern: self.find(path, &stem).map(|(p, _)| p.pattern.name.clone()),
+            metadata: self.parse(path),
+        }
+    }
+
+    pub fn parse(&self, path: &Path) -> FileMetadata {
+        let file_name = fattura_pa::document_stem(path).to_uppercase();
+        let mut meta = FileMetadata {
+            pdf_name: file_name.clone(),
+            doc_type: if fattura_pa::has_xml_name(path) {
+                "rechnung".to_string()
+            } else {
+                "auftrag".to_string()
+            },
+            ..Default::default()
+        };
+
+        let Some((compiled, caps)) = self.find(path, &file_name) else {
+            meta.warnings = true;
+            return meta;
+        };
+
+        let format = compiled
+            .pattern
+            .date_format
+            .as_deref()
+            .filter(|f| !f.trim().is_empty())
+            .unwrap_or(DEFAULT_DATE_FORMAT);
+        meta.doc_type = compiled.pattern.doc_type.clone();
+        meta.nummer_auftrag = text_field(&caps, "nummerAuftrag");
+        meta.kunde = text_field(&caps, "kunde");
+        meta.lieferant = text_field(&caps, "lieferant");
+        meta.datum_auftrag = date_field(&caps, "datumAuftrag", format);
+        meta.datum_rechnung = date_field(&caps, "datumRechnung", format);
+        meta.datum_ddt = date_field(&caps, "datumDdt", format);
+
+        let datum = match meta.doc_type.as_str() {
+            "rechnung" | "gutschrift" => &meta.datum_rechnung,
+            "lieferschein" => &meta.datum_ddt,
+            _ => &meta.datum_auftrag,
+        };
+        meta.warnings = datum.is_none()
+            || meta.nummer_auftrag.is_none()
+            || meta.kunde.is_none()
+            || meta.lieferant.is_none();
+        meta
+    }
+}
+
+impl Default for FilenameSchema {
+    fn default() -> Self {
+        FilenameSchema::new(default_patterns()).expect("default filename patterns compile")
+    }
+}
+
+pub fn load_patterns(settings: &Settings) -> Vec<FilenamePattern> {
+    settings
+        .get_as("filenamePatterns")
+        .filter(|p: &Vec<FilenamePattern>| !p.is_empty())
+        .unwrap_or_else(default_patterns)
+}
+
+pub fn save_patterns(app: &AppHandle, patterns: &[FilenamePattern]) -> Result<(), String> {
+    FilenameSchema::new(patterns.to_vec())?;
+
+    let store = app
+        .store("settings.json")
+        .map_err(|e| format!("Store errore: {}", e))?;
+
+    store.set("filenamePatterns", json!(patterns));
+    store
+        .save()
+        .map_err(|e| format!("Errore di memoria: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(schema: &FilenameSchema, name: &str) -> FileMetadata {
+        schema.parse(Path::new(name))
+    }
+
+    fn user_pattern(name: &str, pattern: &str, date_format: Option<&str>) -> FilenamePattern {
+        FilenamePattern {
+            date_format: date_format.map(str::to_string),
+            ..FilenamePattern::new(name, "auftrag", pattern, &[])
+        }
+    }
+
+    // Names as split by the former updateFileUI: ORDER_DATE_SUPPLIER-CUSTOMER for
+    // orders, FT_SUPPLIER_DATE-CUSTOMER_ORDER for invoices.
+    #[test]
+    fn default_patterns_split_legacy_order_names() {
+        let meta = parse(
+            &FilenameSchema::default(),
+            "/ordini/4500123_20260915_Rossi-Bianchi.pdf",
+        );
+        assert_eq!(meta.pdf_name, "4500123_20260915_ROSSI-BIANCHI");
+        assert_eq!(meta.doc_type, "auftrag");
+        assert_eq!(meta.nummer_auftrag.as_deref(), Some("4500123"));
+        assert_eq!(meta.datum_auftrag.as_deref(), Some("15.09.2026"));
+        assert_eq!(meta.lieferant.as_deref(), Some("ROSSI"));
+        assert_eq!(meta.kunde.as_deref(), Some("BIANCHI"));
+        assert!(!meta.warnings);
+
+        let meta = parse(&FilenameSchema::default(), "4500123_20260915_ROSSI.pdf");
+        assert_eq!(meta.lieferant.as_deref(), Some("ROSSI"));
+        assert_eq!(meta.kunde, None);
+        assert!(meta.warnings);
+    }
+
+    #[test]
+    fn default_patterns_split_legacy_invoice_names() {
+        let meta = parse(
+            &FilenameSchema::default(),
+            "FT_ROSSI_20261005-BIANCHI_4500123.pdf",
+        );
+        assert_eq!(meta.doc_type, "rechnung");
+        assert_eq!(meta.lieferant.as_deref(), Some("ROSSI"));
+        assert_eq!(meta.datum_rechnung.as_deref(), Some("05.10.2026"));
+        assert_eq!(meta.kunde.as_deref(), Some("BIANCHI"));
+        assert_eq!(meta.nummer_auftrag.as_deref(), Some("4500123"));
+        assert!(!meta.warnings);
+
+        let meta = parse(&FilenameSchema::default(), "FT_ROSSI_20261005-BIANCHI.pdf");
+        assert_eq!(meta.nummer_auftrag, None);
+        assert!(meta.warnings);
+
+        let meta = parse(
+            &FilenameSchema::default(),
+            "NC_ROSSI_20261012-BIANCHI_4500123.pdf",
+        );
+        assert_eq!(meta.doc_type, "gutschrift");
+        assert_eq!(meta.datum_rechnung.as_deref(), Some("12.10.2026"));
+
+        let meta = parse(
+            &FilenameSchema::default(),
+            "DDT_ROSSI_20261001-BIANCHI_4500123.pdf",
+        );
+        assert_eq!(meta.doc_type, "lieferschein");
+        assert_eq!(meta.datum_ddt.as_deref(), Some("01.10.2026"));
+    }
+
+    #[test]
+    fn invoice_names_without_underscore_stay_invoices() {
+        let result = FilenameSchema::default().test("FT2026-118.pdf");
+        assert_eq!(result.pattern.as_deref(), Some("Fattura"));
+        assert_eq!(result.metadata.doc_type, "rechnung");
+        assert_eq!(result.metadata.lieferant, None);
+        assert_eq!(result.metadata.nummer_auftrag, None);
+        assert!(result.metadata.warnings);
+    }
+
+    #[test]
+    fn sdi_names_only_set_the_document_type() {
+        let schema = FilenameSchema::default();
+        for name in ["IT01234567890_00042.xml", "IT01234567890_00042.xml.p7m"] {
+            let result = schema.test(name);
+            assert_eq!(result.pattern.as_deref(), Some("Fattura elettronica"));
+            assert_eq!(result.metadata.doc_type, "rechnung");
+            assert_eq!(result.metadata.lieferant, None);
+            assert_eq!(result.metadata.kunde, None);
+            assert_eq!(result.metadata.nummer_auftrag, None);
+        }
+
+        let signed_pdf = schema.test("4500123_20260915_ROSSI-BIANCHI.pdf.p7m");
+        assert_eq!(signed_pdf.pattern.as_deref(), Some("Ordine"));
+        let pdf = schema.test("IT01234567890_00042.pdf");
+        assert_eq!(pdf.pattern.as_deref(), Some("Ordine"));
+    }
+
+    #[test]
+    fn user_patterns_use_named_groups_and_their_date_format() {
+        let schema = FilenameSchema::new(vec![user_pattern(
+            "Bestellung",
+            r"^(?P<lieferant>[A-Z]+)-ORD(?P<nummerAuftrag>\d+)-(?P<datumAuftrag>[\d.]+)(?:-(?P<kunde>.+))?$",
+            Some("%d.%m.%Y"),
+        )])
+        .unwrap();
+
+        let meta = parse(&schema, "huber-ORD0815-03.10.2026-Meier.pdf");
+        assert_eq!(meta.lieferant.as_deref(), Some("HUBER"));
+        assert_eq!(meta.nummer_auftrag.as_deref(), Some("0815"));
+        assert_eq!(meta.datum_auftrag.as_deref(), Some("03.10.2026"));
+        assert_eq!(meta.kunde.as_deref(), Some("MEIER"));
+        assert!(!meta.warnings);
+
+        // A date in another format than the configured one is left empty.
+        let meta = parse(&schema, "huber-ORD0815-2026.10.03.pdf");
+        assert_eq!(meta.nummer_auftrag.as_deref(), Some("0815"));
+        assert_eq!(meta.datum_auftrag, None);
+    }
+
+    #[test]
+    fn first_matching_pattern_wins_and_unmatched_names_are_flagged() {
+        let mut patterns = vec![user_pattern(
+            "Cliente speciale",
+            r"^SPECIAL_(?P<nummerAuftrag>[^_]+)",
+            None,
+        )];
+        patterns.extend(default_patterns());
+        let schema = FilenameSchema::new(patterns).unwrap();
+        assert_eq!(
+            schema
+                .test("SPECIAL_4500123_20260915.pdf")
+                .pattern
+                .as_deref(),
+            Some("Cliente speciale")
+        );
+        assert_eq!(
+            schema
+                .test("4500123_20260915_ROSSI-BIANCHI.pdf")
+                .pattern
+                .as_deref(),
+            Some("Ordine")
+        );
+
+        let schema = FilenameSchema::new(vec![user_pattern(
+            "Cliente speciale",
+            r"^SPECIAL_(?P<nummerAuftrag>[^_]+)",
+            None,
+        )])
+        .unwrap();
+        let result = schema.test("4500123_20260915_ROSSI-BIANCHI.pdf");
+        assert_eq!(result.pattern, None);
+        assert_eq!(result.metadata.doc_type, "auftrag");
+        assert_eq!(result.metadata.nummer_auftrag, None);
+        assert!(result.metadata.warnings);
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected_with_their_name() {
+        let error = FilenameSchema::new(vec![user_pattern("Rotto", r"^(?P<lieferant>", None)])
+            .err()
+            .unwrap();
+        assert!(error.contains("«Rotto»"), "{}", error);
+
+        let mut wrong_type = user_pattern("Tipo", "^X", None);
+        wrong_type.doc_type = "preventivo".to_string();
+        assert!(FilenameSchema::new(vec![wrong_type])
+            .err()
+            .unwrap()
+            .contains("preventivo"));
+    }
+}
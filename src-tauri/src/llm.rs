@@ -0,0 +1,233 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+use crate::retry::Http;
+use crate::settings::Settings;
+use crate::usage::UsageEntry;
+
+const MISTRAL_CHAT_URL: &str = "https://api.mistral.ai/v1/chat/completions";
+const MISTRAL_DEFAULT_MODEL: &str = "mistral-large-latest";
+const OPENAI_DEFAULT_URL: &str = "https://api.openai.com/v1";
+const OPENAI_DEFAULT_MODEL: &str = "gpt-4o-mini";
+const LOCAL_DEFAULT_URL: &str = "http://localhost:11434/v1";
+const LOCAL_DEFAULT_MODEL: &str = "llama3.1";
+
+pub const MISTRAL_KEY_ENTRY: &str = "mistral_api_key";
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum LlmProviderKind {
+    Mistral,
+    OpenAi,
+    Local,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LlmProfile {
+    pub name: String,
+    pub provider: LlmProviderKind,
+    pub base_url: Option<String>,
+    pub model: Option<String>,
+}
+
+impl Default for LlmProfile {
+    fn default() -> Self {
+        LlmProfile {
+            name: "Mistral".to_string(),
+            provider: LlmProviderKind::Mistral,
+            base_url: None,
+            model: None,
+        }
+    }
+}
+
+impl LlmProfile {
+    // OpenAI-compatible profiles point at user-entered URLs, so each keeps its own key
+    // instead of receiving the Mistral one.
+    pub fn key_entry(&self) -> Option<String> {
+        match self.provider {
+            LlmProviderKind::Mistral => Some(MISTRAL_KEY_ENTRY.to_string()),
+            LlmProviderKind::OpenAi => Some(profile_key_entry(&self.name)),
+            LlmProviderKind::Local => None,
+        }
+    }
+}
+
+pub fn profile_key_entry(name: &str) -> String {
+    format!("llm_api_key:{}", name)
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    async fn complete_json(&self, http: &Http, prompt: &str) -> Result<Value, String>;
+}
+
+pub struct MistralProvider {
+    api_key: String,
+    model: String,
+}
+
+pub struct OpenAiCompatibleProvider {
+    profile: String,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+pub struct LocalProvider {
+    base_url: String,
+    model: String,
+}
+
+#[async_trait]
+impl LlmProvider for MistralProvider {
+    async fn complete_json(&self, http: &Http, prompt: &str) -> Result<Value, String> {
+        if self.api_key.trim().is_empty() {
+            return Err("La chiave API Mistral è vuota. Inserirla nelle impostazioni.".to_string());
+        }
+        chat_completion(
+            http,
+            MISTRAL_CHAT_URL,
+            Some(&self.api_key),
+            &self.model,
+            prompt,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    async fn complete_json(&self, http: &Http, prompt: &str) -> Result<Value, String> {
+        if self.api_key.trim().is_empty() {
+            return Err(format!(
+                "La chiave API del profilo «{}» è vuota. Inserirla nelle impostazioni.",
+                self.profile
+            ));
+        }
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        chat_completion(http, &url, Some(&self.api_key), &self.model, prompt).await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LocalProvider {
+    async fn complete_json(&self, http: &Http, prompt: &str) -> Result<Value, String> {
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        chat_completion(http, &url, None, &self.model, prompt).await
+    }
+}
+
+pub fn build_provider(profile: &LlmProfile, api_key: &str) -> Box<dyn LlmProvider> {
+    let base_url = profile.base_url.clone().filter(|u| !u.trim().is_empty());
+    let model = profile.model.clone().filter(|m| !m.trim().is_empty());
+
+    match profile.provider {
+        LlmProviderKind::Mistral => Box::new(MistralProvider {
+            api_key: api_key.to_string(),
+            model: model.unwrap_or_else(|| MISTRAL_DEFAULT_MODEL.to_string()),
+        }),
+        LlmProviderKind::OpenAi => Box::new(OpenAiCompatibleProvider {
+            profile: profile.name.clone(),
+            base_url: base_url.unwrap_or_else(|| OPENAI_DEFAULT_URL.to_string()),
+            api_key: api_key.to_string(),
+            model: model.unwrap_or_else(|| OPENAI_DEFAULT_MODEL.to_string()),
+        }),
+        LlmProviderKind::Local => Box::new(LocalProvider {
+            base_url: base_url.unwrap_or_else(|| LOCAL_DEFAULT_URL.to_string()),
+            model: model.unwrap_or_else(|| LOCAL_DEFAULT_MODEL.to_string()),
+        }),
+    }
+}
+
+async fn chat_completion(
+    http: &Http,
+    url: &str,
+    api_key: Option<&str>,
+    model: &str,
+    prompt: &str,
+) -> Result<Value, String> {
+    let body = json!({
+        "model": model,
+        "messages": [
+            { "role": "user", "content": prompt }
+        ],
+        "response_format": { "type": "json_object" }
+    });
+
+    let mut request = http.post(url).json(&body);
+    if let Some(key) = api_key {
+        request = request.header("Authorization", format!("Bearer {}", key));
+    }
+
+    let res = http
+        .send(request)
+        .await
+        .map_err(|e| format!("Errore richiesta API: {}", e))?;
+
+    if !res.status().is_success() {
+        return Err(format!("Errore di stato API: {}", res.status()));
+    }
+
+    let json_res: Value = res
+        .json()
+        .await
+        .map_err(|e| format!("JSON Fehler: {}", e))?;
+
+    if let Some(usage) = json_res.get("usage") {
+        http.record_usage(UsageEntry::llm(model, usage));
+    }
+
+    let content_str = json_res["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or("Nessun contenuto nella risposta")?;
+
+    // Local models tend to wrap the object in a markdown code fence despite response_format.
+    let cleaned = content_str
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+
+    serde_json::from_str(cleaned).map_err(|e| format!("Errore di analisi JSON: {}", e))
+}
+
+pub fn load_profiles(settings: &Settings) -> (Vec<LlmProfile>, String) {
+    let mut profiles: Vec<LlmProfile> = settings.get_as("llmProfiles").unwrap_or_default();
+
+    if profiles.is_empty() {
+        profiles.push(LlmProfile::default());
+    }
+
+    let active = settings
+        .get("activeLlmProfile")
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .filter(|name| profiles.iter().any(|p| &p.name == name))
+        .unwrap_or_else(|| profiles[0].name.clone());
+
+    (profiles, active)
+}
+
+pub fn load_active_profile(settings: &Settings) -> LlmProfile {
+    let (profiles, active) = load_profiles(settings);
+    profiles
+        .into_iter()
+        .find(|p| p.name == active)
+        .unwrap_or_default()
+}
+
+pub fn save_profiles(app: &AppHandle, profiles: &[LlmProfile], active: &str) -> Result<(), String> {
+    let store = app
+        .store("settings.json")
+        .map_err(|e| format!("Store errore: {}", e))?;
+
+    store.set("llmProfiles", json!(profiles));
+    store.set("activeLlmProfile", json!(active));
+    store
+        .save()
+        .map_err(|e| format!("Errore di memoria: {}", e))
+}
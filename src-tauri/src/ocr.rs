@@ -0,0 +1,244 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::retry::Http;
+use crate::settings::Settings;
+use crate::usage::UsageEntry;
+
+pub const MARKDOWN_LAYOUT: &str =
+    "THE LAYOUT IS MARKDOWN. Tables are marked with pipes '|'. Use this structure.";
+const MISTRAL_OCR_MODEL: &str = "mistral-ocr-latest";
+pub const WHITESPACE_LAYOUT: &str = "THE LAYOUT IS 'WHITESPACE'. Columns are separated only by spaces. There are no lines. Visualize the columns.";
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum OcrBackendKind {
+    #[default]
+    Mistral,
+    Tesseract,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OcrSettings {
+    pub backend: OcrBackendKind,
+    pub tesseract_path: String,
+    pub pdftoppm_path: String,
+    pub languages: String,
+    pub dpi: u32,
+}
+
+impl Default for OcrSettings {
+    fn default() -> Self {
+        OcrSettings {
+            backend: OcrBackendKind::Mistral,
+            tesseract_path: "tesseract".to_string(),
+            pdftoppm_path: "pdftoppm".to_string(),
+            languages: "ita+deu+eng".to_string(),
+            dpi: 300,
+        }
+    }
+}
+
+#[async_trait]
+pub trait OcrBackend: Send + Sync {
+    async fn recognize(&self, http: &Http, path: &str) -> Result<String, String>;
+
+    fn layout_instruction(&self) -> &'static str;
+}
+
+pub struct MistralOcr {
+    api_key: String,
+}
+
+pub struct TesseractOcr {
+    settings: OcrSettings,
+}
+
+#[async_trait]
+impl OcrBackend for MistralOcr {
+    async fn recognize(&self, http: &Http, path: &str) -> Result<String, String> {
+        if self.api_key.trim().is_empty() {
+            return Err(
+                "L'OCR Mistral richiede la chiave API Mistral. Inserirla nelle impostazioni oppure scegliere Tesseract."
+                    .to_string(),
+            );
+        }
+        let file_bytes =
+            fs::read(path).map_err(|e| format!("Impossibile leggere il file: {}", e))?;
+        let b64_doc = general_purpose::STANDARD.encode(file_bytes);
+
+        let ocr_body = json!({
+            "model": MISTRAL_OCR_MODEL,
+            "document": {
+                "type": "document_url",
+                "document_url": format!("data:application/pdf;base64,{}", b64_doc)
+            }
+        });
+
+        let request = http
+            .post("https://api.mistral.ai/v1/ocr")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&ocr_body);
+        let ocr_res = http
+            .send(request)
+            .await
+            .map_err(|e| format!("Richiesta OCR non riuscita: {}", e))?;
+
+        if !ocr_res.status().is_success() {
+            return Err(format!("Stato Mistral OCR: {}", ocr_res.status()));
+        }
+
+        let ocr_json: Value = ocr_res.json().await.map_err(|e| e.to_string())?;
+
+        let pages_processed = ocr_json
+            .pointer("/usage_info/pages_processed")
+            .and_then(|p| p.as_u64())
+            .or_else(|| {
+                ocr_json
+                    .get("pages")
+                    .and_then(|p| p.as_array())
+                    .map(|p| p.len() as u64)
+            })
+            .unwrap_or(0);
+        http.record_usage(UsageEntry::ocr(MISTRAL_OCR_MODEL, pages_processed));
+
+        if let Some(pages) = ocr_json.get("pages").and_then(|p| p.as_array()) {
+            let text = pages
+                .iter()
+                .filter_map(|p| p.get("markdown").and_then(|m| m.as_str()))
+                .collect::<Vec<&str>>()
+                .join("\n\n");
+
+            if text.trim().is_empty() {
+                return Err("Il risultato OCR era vuoto".to_string());
+            }
+            Ok(text)
+        } else {
+            Err("Nessuna pagina nel risultato OCR".to_string())
+        }
+    }
+
+    fn layout_instruction(&self) -> &'static str {
+        MARKDOWN_LAYOUT
+    }
+}
+
+#[async_trait]
+impl OcrBackend for TesseractOcr {
+    async fn recognize(&self, http: &Http, path: &str) -> Result<String, String> {
+        let settings = self.settings.clone();
+        let path = path.to_string();
+        let (text, pages) =
+            tauri::async_runtime::spawn_blocking(move || run_tesseract(&settings, &path))
+                .await
+                .map_err(|e| format!("OCR locale interrotto: {}", e))??;
+        http.record_usage(UsageEntry::ocr("tesseract", pages as u64));
+        Ok(text)
+    }
+
+    fn layout_instruction(&self) -> &'static str {
+        WHITESPACE_LAYOUT
+    }
+}
+
+fn run_tesseract(settings: &OcrSettings, path: &str) -> Result<(String, usize), String> {
+    let work_dir = std::env::temp_dir().join(format!(
+        "maggus-ocr-{}-{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    fs::create_dir_all(&work_dir)
+        .map_err(|e| format!("Impossibile creare la cartella temporanea: {}", e))?;
+
+    let result = rasterize_and_recognize(settings, path, &work_dir);
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+fn rasterize_and_recognize(
+    settings: &OcrSettings,
+    path: &str,
+    work_dir: &Path,
+) -> Result<(String, usize), String> {
+    let output = Command::new(&settings.pdftoppm_path)
+        .arg("-r")
+        .arg(settings.dpi.to_string())
+        .arg("-png")
+        .arg(path)
+        .arg(work_dir.join("page"))
+        .output()
+        .map_err(|e| format!("Impossibile eseguire pdftoppm: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "pdftoppm Exit Code: {:?} ({})",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut pages: Vec<PathBuf> = fs::read_dir(work_dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "png"))
+        .collect();
+    pages.sort();
+
+    if pages.is_empty() {
+        return Err("Nessuna pagina nel risultato OCR".to_string());
+    }
+
+    let mut texts = Vec::with_capacity(pages.len());
+    for page in &pages {
+        let output = Command::new(&settings.tesseract_path)
+            .arg(page)
+            .arg("stdout")
+            .arg("-l")
+            .arg(&settings.languages)
+            .arg("--psm")
+            .arg("6")
+            .arg("-c")
+            .arg("preserve_interword_spaces=1")
+            .output()
+            .map_err(|e| format!("Impossibile eseguire Tesseract: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "Tesseract Exit Code: {:?} ({})",
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        texts.push(
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    let text = texts.join("\n\n");
+    if text.trim().is_empty() {
+        return Err("Il risultato OCR era vuoto".to_string());
+    }
+    Ok((text, pages.len()))
+}
+
+pub fn load_settings(settings: &Settings) -> OcrSettings {
+    settings.get_as("ocr").unwrap_or_default()
+}
+
+pub fn build_backend(settings: &OcrSettings, api_key: &str) -> Box<dyn OcrBackend> {
+    match settings.backend {
+        OcrBackendKind::Mistral => Box::new(MistralOcr {
+            api_key: api_key.to_string(),
+        }),
+        OcrBackendKind::Tesseract => Box::new(TesseractOcr {
+            settings: settings.clone(),
+        }),
+    }
+}
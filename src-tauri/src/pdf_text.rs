@@ -0,0 +1,237 @@
+use async_trait::async_trait;
+use pdf_oxide::converters::ConversionOptions;
+use pdf_oxide::layout::TextSpan;
+use pdf_oxide::PdfDocument;
+use std::process::Command;
+use tauri::AppHandle;
+use tauri_plugin_shell::ShellExt;
+
+use crate::settings::Settings;
+
+pub const MIN_TEXT_LEN: usize = 50;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextMode {
+    Plain,
+    Layout,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PdfTextBackend {
+    #[default]
+    Native,
+    Pdftotext,
+}
+
+#[async_trait]
+pub trait PdfTextExtractor: Send + Sync {
+    async fn extract(&self, path: &str, mode: TextMode) -> Result<String, String>;
+}
+
+pub struct NativeExtractor;
+
+pub struct SidecarExtractor {
+    pub app: AppHandle,
+}
+
+pub struct SystemPdftotextExtractor {
+    pub binary: String,
+}
+
+#[async_trait]
+impl PdfTextExtractor for NativeExtractor {
+    async fn extract(&self, path: &str, mode: TextMode) -> Result<String, String> {
+        let path = path.to_string();
+        tauri::async_runtime::spawn_blocking(move || extract_native(&path, mode))
+            .await
+            .map_err(|e| format!("Estrazione testo interrotta: {}", e))?
+    }
+}
+
+#[async_trait]
+impl PdfTextExtractor for SidecarExtractor {
+    async fn extract(&self, path: &str, mode: TextMode) -> Result<String, String> {
+        let mut args = vec!["-enc", "UTF-8"];
+        if mode == TextMode::Layout {
+            args.push("-layout");
+        }
+        args.push(path);
+        args.push("-");
+
+        let sidecar_command = self
+            .app
+            .shell()
+            .sidecar("pdftotext")
+            .map_err(|e| format!("Errore di configurazione del sidecar: {}", e))?
+            .args(&args);
+
+        let output = sidecar_command
+            .output()
+            .await
+            .map_err(|e| format!("Impossibile eseguire Sidecar: {}", e))?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!("Sidecar Exit Code: {:?}", output.status.code()))
+        }
+    }
+}
+
+#[async_trait]
+impl PdfTextExtractor for SystemPdftotextExtractor {
+    async fn extract(&self, path: &str, mode: TextMode) -> Result<String, String> {
+        let mut command = Command::new(&self.binary);
+        command.arg("-enc").arg("UTF-8");
+        if mode == TextMode::Layout {
+            command.arg("-layout");
+        }
+        command.arg(path).arg("-");
+
+        let output = tauri::async_runtime::spawn_blocking(move || command.output())
+            .await
+            .map_err(|e| format!("Estrazione testo interrotta: {}", e))?
+            .map_err(|e| format!("Impossibile eseguire {}: {}", self.binary, e))?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!("pdftotext Exit Code: {:?}", output.status.code()))
+        }
+    }
+}
+
+fn extract_native(path: &str, mode: TextMode) -> Result<String, String> {
+    let mut doc =
+        PdfDocument::open(path).map_err(|e| format!("Impossibile aprire il PDF: {}", e))?;
+    let page_count = doc
+        .page_count()
+        .map_err(|e| format!("Struttura PDF non valida: {}", e))?;
+
+    let options = ConversionOptions::default();
+    let mut pages = Vec::with_capacity(page_count);
+
+    for page in 0..page_count {
+        let text = match mode {
+            TextMode::Plain => doc.to_plain_text(page, &options),
+            TextMode::Layout => doc.extract_spans(page).map(layout_page),
+        }
+        .map_err(|e| format!("Estrazione pagina {} fallita: {}", page + 1, e))?;
+        pages.push(text);
+    }
+
+    Ok(pages.join("\n\n"))
+}
+
+fn layout_page(mut spans: Vec<TextSpan>) -> String {
+    spans.retain(|s| !s.text.trim().is_empty());
+    if spans.is_empty() {
+        return String::new();
+    }
+
+    spans.sort_by(|a, b| {
+        b.bbox
+            .y
+            .partial_cmp(&a.bbox.y)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(
+                a.bbox
+                    .x
+                    .partial_cmp(&b.bbox.x)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+    });
+
+    let (total_width, total_chars) = spans.iter().fold((0.0f32, 0usize), |acc, s| {
+        (acc.0 + s.bbox.width, acc.1 + s.text.chars().count())
+    });
+    let char_width = if total_chars > 0 && total_width > 0.0 {
+        total_width / total_chars as f32
+    } else {
+        5.0
+    };
+    let min_x = spans.iter().map(|s| s.bbox.x).fold(f32::INFINITY, f32::min);
+
+    let mut lines: Vec<(f32, Vec<&TextSpan>)> = Vec::new();
+    for span in &spans {
+        let tolerance = (span.font_size * 0.5).max(2.0);
+        match lines.last_mut() {
+            Some((y, line)) if (*y - span.bbox.y).abs() <= tolerance => line.push(span),
+            _ => lines.push((span.bbox.y, vec![span])),
+        }
+    }
+
+    let mut out = String::new();
+    for (_, mut line) in lines {
+        line.sort_by(|a, b| {
+            a.bbox
+                .x
+                .partial_cmp(&b.bbox.x)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut text = String::new();
+        for span in line {
+            let column = ((span.bbox.x - min_x) / char_width).round().max(0.0) as usize;
+            let current = text.chars().count();
+            if column > current {
+                text.push_str(&" ".repeat(column - current));
+            } else if current > 0 {
+                text.push(' ');
+            }
+            text.push_str(span.text.trim());
+        }
+        out.push_str(text.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+pub fn build_extractors(
+    settings: &Settings,
+    pdftotext: Box<dyn PdfTextExtractor>,
+) -> Vec<Box<dyn PdfTextExtractor>> {
+    let backend: PdfTextBackend = settings.get_as("pdfTextBackend").unwrap_or_default();
+    let fallback = settings
+        .get("pdftotextFallback")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+
+    let native: Box<dyn PdfTextExtractor> = Box::new(NativeExtractor);
+    match backend {
+        PdfTextBackend::Native if fallback => vec![native, pdftotext],
+        PdfTextBackend::Native => vec![native],
+        PdfTextBackend::Pdftotext => vec![pdftotext, native],
+    }
+}
+
+pub async fn extract_text(
+    extractors: &[Box<dyn PdfTextExtractor>],
+    path: &str,
+    mode: TextMode,
+) -> Result<String, String> {
+    let mut best: Option<String> = None;
+    let mut last_error = String::from("Nessun backend di estrazione configurato");
+
+    for extractor in extractors {
+        match extractor.extract(path, mode).await {
+            Ok(text) if text.trim().len() >= MIN_TEXT_LEN => return Ok(text),
+            Ok(text) => {
+                if best
+                    .as_ref()
+                    .is_none_or(|b| text.trim().len() > b.trim().len())
+                {
+                    best = Some(text);
+                }
+            }
+            Err(e) => {
+                println!("Estrazione testo non riuscita: {}", e);
+                last_error = e;
+            }
+        }
+    }
+
+    best.ok_or(last_error)
+}
@@ -0,0 +1,246 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::settings::Settings;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProductMatchSettings {
+    pub enabled: bool,
+    pub threshold: f64,
+    pub ambiguity_margin: f64,
+}
+
+impl Default for ProductMatchSettings {
+    fn default() -> Self {
+        ProductMatchSettings {
+            enabled: true,
+            threshold: 0.85,
+            ambiguity_margin: 0.05,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchCandidate {
+    pub row: u32,
+    pub produkt: String,
+    pub score: f64,
+}
+
+pub enum ProductMatch {
+    Found(MatchCandidate),
+    Ambiguous(Vec<MatchCandidate>),
+    NotFound,
+}
+
+#[derive(Clone, Default)]
+pub struct ProductMatcher {
+    settings: ProductMatchSettings,
+    corrections: HashMap<String, String>,
+}
+
+pub fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn token_set_ratio(a: &str, b: &str) -> f64 {
+    let a: BTreeSet<&str> = a.split(' ').filter(|t| !t.is_empty()).collect();
+    let b: BTreeSet<&str> = b.split(' ').filter(|t| !t.is_empty()).collect();
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let join = |tokens: Vec<&str>| tokens.join(" ");
+    let common = join(a.intersection(&b).copied().collect());
+    let with = |rest: Vec<&str>| {
+        let rest = join(rest);
+        format!("{} {}", common, rest).trim().to_string()
+    };
+    let only_a = with(a.difference(&b).copied().collect());
+    let only_b = with(b.difference(&a).copied().collect());
+
+    let ratio = |x: &str, y: &str| {
+        if x.is_empty() || y.is_empty() {
+            0.0
+        } else {
+            strsim::normalized_levenshtein(x, y)
+        }
+    };
+    // A name that only adds words to the other is a different product ("OLIO" and
+    // "OLIO ROSMARINO"), so the full names are compared instead of the shared part.
+    if a.is_subset(&b) || b.is_subset(&a) {
+        return ratio(&only_a, &only_b);
+    }
+    ratio(&common, &only_a)
+        .max(ratio(&common, &only_b))
+        .max(ratio(&only_a, &only_b))
+}
+
+impl ProductMatcher {
+    pub fn new(settings: ProductMatchSettings, corrections: &HashMap<String, String>) -> Self {
+        ProductMatcher {
+            settings,
+            corrections: corrections
+                .iter()
+                .map(|(wrong, correct)| (normalize(wrong), normalize(correct)))
+                .collect(),
+        }
+    }
+
+    fn canonical(&self, name: &str) -> String {
+        let name = normalize(name);
+        self.corrections.get(&name).cloned().unwrap_or(name)
+    }
+
+    pub fn score(&self, a: &str, b: &str) -> f64 {
+        let (a, b) = (self.canonical(a), self.canonical(b));
+        if a == b {
+            return 1.0;
+        }
+        token_set_ratio(&a, &b)
+    }
+
+    pub fn find(&self, produkt: &str, candidates: &[(u32, String)]) -> ProductMatch {
+        let mut scored: Vec<MatchCandidate> = candidates
+            .iter()
+            .map(|(row, name)| MatchCandidate {
+                row: *row,
+                produkt: name.clone(),
+                score: self.score(produkt, name),
+            })
+            .filter(|c| c.score >= self.settings.threshold)
+            .collect();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        match scored.as_slice() {
+            [] => ProductMatch::NotFound,
+            [best] => ProductMatch::Found(best.clone()),
+            [best, second, ..] if best.score - second.score > self.settings.ambiguity_margin => {
+                ProductMatch::Found(best.clone())
+            }
+            _ => {
+                let best = scored[0].score;
+                scored.retain(|c| best - c.score <= self.settings.ambiguity_margin);
+                ProductMatch::Ambiguous(scored)
+            }
+        }
+    }
+}
+
+pub fn load(settings: &Settings, corrections: &Settings) -> Option<ProductMatcher> {
+    let match_settings: ProductMatchSettings =
+        settings.get_as("productMatching").unwrap_or_default();
+    if !match_settings.enabled {
+        return None;
+    }
+    let corrections: HashMap<String, String> = corrections
+        .get_as("product_corrections")
+        .unwrap_or_default();
+    Some(ProductMatcher::new(match_settings, &corrections))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(threshold: f64) -> ProductMatcher {
+        let settings = ProductMatchSettings {
+            threshold,
+            ..Default::default()
+        };
+        ProductMatcher::new(settings, &HashMap::new())
+    }
+
+    fn candidates(names: &[&str]) -> Vec<(u32, String)> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (i as u32 + 2, n.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn word_order_and_punctuation_do_not_matter() {
+        let matcher = matcher(0.85);
+        assert_eq!(
+            matcher.score("SEMI CARDO MARIANO", "CARDO MARIANO SEMI"),
+            1.0
+        );
+        assert_eq!(
+            matcher.score("Cardo-Mariano, semi", "CARDO MARIANO SEMI"),
+            1.0
+        );
+        let ProductMatch::Found(found) = matcher.find(
+            "SEMI CARDO MARIANO",
+            &candidates(&["OLIO ROSMARINO", "CARDO MARIANO SEMI"]),
+        ) else {
+            panic!("expected a match");
+        };
+        assert_eq!(found.row, 3);
+    }
+
+    #[test]
+    fn added_words_are_a_different_product() {
+        let matcher = matcher(0.85);
+        assert!(matcher.score("OLIO", "OLIO ROSMARINO") < 0.5);
+        assert!(matches!(
+            matcher.find("OLIO", &candidates(&["OLIO ROSMARINO"])),
+            ProductMatch::NotFound
+        ));
+        assert!(matches!(
+            matcher.find("OLIO ROSMARINO BIO", &candidates(&["OLIO ROSMARINO"])),
+            ProductMatch::NotFound
+        ));
+    }
+
+    #[test]
+    fn scores_below_the_threshold_are_not_matched() {
+        let typo = "CARDO MARIANO SEMl";
+        let score = matcher(0.85).score(typo, "CARDO MARIANO SEMI");
+        assert!(score > 0.9 && score < 0.95, "{}", score);
+
+        let rows = candidates(&["CARDO MARIANO SEMI"]);
+        assert!(matches!(
+            matcher(0.9).find(typo, &rows),
+            ProductMatch::Found(_)
+        ));
+        assert!(matches!(
+            matcher(0.95).find(typo, &rows),
+            ProductMatch::NotFound
+        ));
+    }
+
+    #[test]
+    fn close_runners_up_make_the_match_ambiguous() {
+        let matcher = matcher(0.85);
+        let rows = candidates(&["CARDO MARIANO SEMI", "CARDO MARIANO SEME", "OLIO"]);
+
+        let ProductMatch::Ambiguous(ambiguous) = matcher.find("CARDO MARIANO SEMl", &rows) else {
+            panic!("expected an ambiguous match");
+        };
+        let rows: Vec<u32> = ambiguous.iter().map(|c| c.row).collect();
+        assert_eq!(rows, [2, 3]);
+
+        // An exact name is more than the margin ahead of the one-letter variant.
+        let ProductMatch::Found(found) = matcher.find(
+            "CARDO MARIANO SEMI",
+            &candidates(&["CARDO MARIANO SEME", "CARDO MARIANO SEMI"]),
+        ) else {
+            panic!("expected a match");
+        };
+        assert_eq!(found.row, 3);
+    }
+
+    #[test]
+    fn learned_corrections_map_names_before_scoring() {
+        let corrections =
+            HashMap::from([("CARDO SEMI".to_string(), "CARDO MARIANO SEMI".to_string())]);
+        let matcher = ProductMatcher::new(ProductMatchSettings::default(), &corrections);
+        assert_eq!(matcher.score("cardo semi", "CARDO MARIANO SEMI"), 1.0);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::settings::Settings;
+
+const UNDER_DELIVERY: &str = "Consegna parziale";
+const OVER_DELIVERY: &str = "Consegna eccessiva";
+const MISSING_INVOICE: &str = "Fattura mancante";
+const MISSING_ORDER: &str = "Ordine non trovato";
+const PRICE_DEVIATION: &str = "Prezzo fattura";
+// Written in front of every note in the sheet, so a refresh only replaces its own notes.
+const NOTE_MARKER: &str = "⚠ ";
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReconcileSettings {
+    pub enabled: bool,
+    pub quantity_tolerance: f64,
+    pub price_tolerance_percent: f64,
+    pub write_notes: bool,
+}
+
+impl Default for ReconcileSettings {
+    fn default() -> Self {
+        ReconcileSettings {
+            enabled: true,
+            quantity_tolerance: 0.0,
+            price_tolerance_percent: 2.0,
+            write_notes: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InvoiceLine {
+    pub nummer_rechnung: Option<String>,
+    pub gelieferte_menge: Option<f64>,
+    pub preis: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReconcileLine {
+    pub row: Option<u32>,
+    pub nummer_auftrag: String,
+    pub produkt: String,
+    pub menge: Option<f64>,
+    pub preis: Option<f64>,
+    pub invoices: Vec<InvoiceLine>,
+}
+
+impl ReconcileLine {
+    pub fn add_invoice(&mut self, invoice: InvoiceLine) {
+        let number = invoice
+            .nummer_rechnung
+            .as_ref()
+            .map(|nr| nr.trim().to_lowercase());
+        let existing = self.invoices.iter_mut().find(|i| {
+            i.nummer_rechnung
+                .as_ref()
+                .map(|nr| nr.trim().to_lowercase())
+                == number
+        });
+
+        if let Some(slot) = existing {
+            *slot = invoice;
+            return;
+        }
+
+        let listed = |i: &InvoiceLine| {
+            let (Some(listed), Some(number)) = (&i.nummer_rechnung, &number) else {
+                return false;
+            };
+            listed
+                .split(',')
+                .any(|nr| nr.trim().to_lowercase() == *number)
+        };
+        if !self.invoices.iter().any(listed) {
+            self.invoices.push(invoice);
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum FindingKind {
+    UnderDelivery,
+    OverDelivery,
+    MissingInvoice,
+    MissingOrder,
+    PriceDeviation,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub nummer_auftrag: String,
+    pub produkt: String,
+    pub row: Option<u32>,
+    pub ordered: Option<f64>,
+    pub delivered: Option<f64>,
+    pub order_price: Option<f64>,
+    pub invoice_price: Option<f64>,
+    pub note: String,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciliationReport {
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl ReconciliationReport {
+    // Findings on lines that are not in the sheet yet, keyed by order and product.
+    pub fn new_line_notes(&self) -> HashMap<(String, String), String> {
+        let mut notes: HashMap<(String, String), String> = HashMap::new();
+        for finding in self.findings.iter().filter(|f| f.row.is_none()) {
+            let key = line_key(&finding.nummer_auftrag, &finding.produkt);
+            push_note(notes.entry(key).or_default(), &marked(&finding.note));
+        }
+        notes
+    }
+
+    // Findings on existing sheet rows, keyed by row.
+    pub fn row_notes(&self) -> HashMap<u32, String> {
+        let mut notes: HashMap<u32, String> = HashMap::new();
+        for finding in &self.findings {
+            if let Some(row) = finding.row {
+                push_note(notes.entry(row).or_default(), &marked(&finding.note));
+            }
+        }
+        notes
+    }
+}
+
+fn marked(note: &str) -> String {
+    format!("{}{}", NOTE_MARKER, note)
+}
+
+fn push_note(notes: &mut String, note: &str) {
+    if !notes.is_empty() {
+        notes.push_str("; ");
+    }
+    notes.push_str(note);
+}
+
+// Replaces the reconciliation notes in a notes cell with the current ones, keeping
+// whatever else the user wrote there.
+pub fn refresh_notes(cell: &str, notes: Option<&str>) -> String {
+    let mut refreshed = String::new();
+    cell.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && !s.starts_with(NOTE_MARKER.trim_end()))
+        .chain(notes)
+        .for_each(|s| push_note(&mut refreshed, s));
+    refreshed
+}
+
+pub fn line_key(nummer_auftrag: &str, produkt: &str) -> (String, String) {
+    (
+        nummer_auftrag.trim().to_lowercase(),
+        produkt.trim().to_lowercase(),
+    )
+}
+
+fn format_number(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0')
+        .trim_end_matches('.')
+        .replace('.', ",")
+}
+
+fn finding(line: &ReconcileLine, kind: FindingKind, note: String) -> Finding {
+    Finding {
+        kind,
+        nummer_auftrag: line.nummer_auftrag.clone(),
+        produkt: line.produkt.clone(),
+        row: line.row,
+        ordered: line.menge,
+        delivered: None,
+        order_price: line.preis,
+        invoice_price: None,
+        note,
+    }
+}
+
+pub fn reconcile(lines: &[ReconcileLine], settings: &ReconcileSettings) -> ReconciliationReport {
+    let invoiced_orders: HashSet<String> = lines
+        .iter()
+        .filter(|l| !l.invoices.is_empty())
+        .map(|l| l.nummer_auftrag.trim().to_lowercase())
+        .collect();
+
+    let mut report = ReconciliationReport::default();
+
+    for line in lines {
+        if line.invoices.is_empty() {
+            if line.menge.is_some()
+                && invoiced_orders.contains(&line.nummer_auftrag.trim().to_lowercase())
+            {
+                report.checked += 1;
+                report.findings.push(finding(
+                    line,
+                    FindingKind::MissingInvoice,
+                    MISSING_INVOICE.to_string(),
+                ));
+            }
+            continue;
+        }
+
+        report.checked += 1;
+
+        let Some(ordered) = line.menge else {
+            report.findings.push(finding(
+                line,
+                FindingKind::MissingOrder,
+                MISSING_ORDER.to_string(),
+            ));
+            continue;
+        };
+
+        let quantities: Vec<f64> = line
+            .invoices
+            .iter()
+            .filter_map(|i| i.gelieferte_menge)
+            .collect();
+        if !quantities.is_empty() {
+            let delivered: f64 = quantities.iter().sum();
+            let difference = delivered - ordered;
+
+            let kind = if difference < -settings.quantity_tolerance {
+                Some((FindingKind::UnderDelivery, UNDER_DELIVERY))
+            } else if difference > settings.quantity_tolerance {
+                Some((FindingKind::OverDelivery, OVER_DELIVERY))
+            } else {
+                None
+            };
+
+            if let Some((kind, label)) = kind {
+                let mut f = finding(
+                    line,
+                    kind,
+                    format!(
+                        "{}: {} di {}",
+                        label,
+                        format_number(delivered),
+                        format_number(ordered)
+                    ),
+                );
+                f.delivered = Some(delivered);
+                report.findings.push(f);
+            }
+        }
+
+        if let Some(order_price) = line.preis.filter(|p| *p != 0.0) {
+            let worst = line
+                .invoices
+                .iter()
+                .filter_map(|i| i.preis)
+                .map(|p| (p, (p - order_price) / order_price * 100.0))
+                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+
+            if let Some((invoice_price, deviation)) = worst {
+                if deviation.abs() > settings.price_tolerance_percent {
+                    let mut f = finding(
+                        line,
+                        FindingKind::PriceDeviation,
+                        format!(
+                            "{} {} invece di {} ({}%)",
+                            PRICE_DEVIATION,
+                            format_number(invoice_price),
+                            format_number(order_price),
+                            format!("{:+.1}", deviation).replace('.', ",")
+                        ),
+                    );
+                    f.invoice_price = Some(invoice_price);
+                    report.findings.push(f);
+                }
+            }
+        }
+    }
+
+    report
+}
+
+pub fn load_settings(settings: &Settings) -> ReconcileSettings {
+    settings.get_as("reconciliation").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(row: u32, nummer: &str, menge: f64, invoices: &[(&str, f64)]) -> ReconcileLine {
+        let mut line = ReconcileLine {
+            row: Some(row),
+            nummer_auftrag: nummer.to_string(),
+            produkt: "Vite M8".to_string(),
+            menge: Some(menge),
+            preis: Some(10.0),
+            invoices: Vec::new(),
+        };
+        for (nummer, delivered) in invoices {
+            line.add_invoice(InvoiceLine {
+                nummer_rechnung: Some(nummer.to_string()),
+                gelieferte_menge: Some(*delivered),
+                preis: Some(10.0),
+            });
+        }
+        line
+    }
+
+    fn kinds(report: &ReconciliationReport) -> Vec<FindingKind> {
+        report.findings.iter().map(|f| f.kind).collect()
+    }
+
+    #[test]
+    fn over_delivery_across_invoices_is_reported() {
+        let report = reconcile(
+            &[line(2, "A-1", 100.0, &[("F-1", 60.0), ("F-2", 50.0)])],
+            &ReconcileSettings::default(),
+        );
+        assert_eq!(kinds(&report), [FindingKind::OverDelivery]);
+        assert_eq!(report.findings[0].delivered, Some(110.0));
+        assert_eq!(report.findings[0].note, "Consegna eccessiva: 110 di 100");
+
+        let tolerant = ReconcileSettings {
+            quantity_tolerance: 10.0,
+            ..Default::default()
+        };
+        let report = reconcile(
+            &[line(2, "A-1", 100.0, &[("F-1", 60.0), ("F-2", 50.0)])],
+            &tolerant,
+        );
+        assert!(report.findings.is_empty());
+    }
+
+    #[test]
+    fn missing_invoice_only_for_orders_with_other_invoiced_lines() {
+        let mut other = line(3, "A-1", 20.0, &[]);
+        other.produkt = "Tassello 10".to_string();
+        let unrelated = line(4, "A-2", 5.0, &[]);
+
+        let report = reconcile(
+            &[line(2, "A-1", 100.0, &[("F-1", 100.0)]), other, unrelated],
+            &ReconcileSettings::default(),
+        );
+        assert_eq!(kinds(&report), [FindingKind::MissingInvoice]);
+        assert_eq!(report.findings[0].row, Some(3));
+        assert_eq!(report.checked, 2);
+    }
+
+    #[test]
+    fn credit_notes_are_subtracted_from_the_delivered_quantity() {
+        let credited = line(2, "A-1", 100.0, &[("F-1", 110.0), ("NC 7", -10.0)]);
+        assert!(reconcile(&[credited], &ReconcileSettings::default())
+            .findings
+            .is_empty());
+
+        let short = line(2, "A-1", 100.0, &[("F-1", 100.0), ("NC 7", -10.0)]);
+        let report = reconcile(&[short], &ReconcileSettings::default());
+        assert_eq!(kinds(&report), [FindingKind::UnderDelivery]);
+        assert_eq!(report.findings[0].note, "Consegna parziale: 90 di 100");
+    }
+
+    #[test]
+    fn price_deviation_uses_decimal_commas() {
+        let mut expensive = line(2, "A-1", 100.0, &[("F-1", 100.0)]);
+        expensive.invoices[0].preis = Some(11.5);
+        let report = reconcile(&[expensive], &ReconcileSettings::default());
+        assert_eq!(kinds(&report), [FindingKind::PriceDeviation]);
+        assert_eq!(
+            report.findings[0].note,
+            "Prezzo fattura 11,5 invece di 10 (+15,0%)"
+        );
+    }
+
+    #[test]
+    fn refreshing_notes_replaces_only_marked_notes() {
+        assert_eq!(
+            refresh_notes(
+                "Urgente; ⚠ Consegna parziale: 50 di 100; NC 7",
+                Some("⚠ Consegna parziale: 80 di 100")
+            ),
+            "Urgente; NC 7; ⚠ Consegna parziale: 80 di 100"
+        );
+        assert_eq!(
+            refresh_notes("⚠ Fattura mancante; Prezzo fattura concordato a 11", None),
+            "Prezzo fattura concordato a 11"
+        );
+        assert_eq!(
+            refresh_notes("", Some("⚠ Fattura mancante")),
+            "⚠ Fattura mancante"
+        );
+    }
+
+    #[test]
+    fn row_notes_are_marked() {
+        let report = reconcile(
+            &[line(2, "A-1", 100.0, &[("F-1", 60.0)])],
+            &ReconcileSettings::default(),
+        );
+        assert_eq!(report.row_notes()[&2], "⚠ Consegna parziale: 60 di 100");
+    }
+}
@@ -0,0 +1,260 @@
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+use crate::settings::Settings;
+use crate::usage::{UsageEntry, UsageStep};
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            timeout_secs: 180,
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << (attempt - 1).min(16))
+            .min(self.max_delay_ms);
+        Duration::from_millis(fastrand::u64(exp / 2..=exp))
+    }
+
+    fn delay(&self, attempt: u32, outcome: &Result<Response, reqwest::Error>) -> Option<Duration> {
+        let delay = match outcome {
+            Ok(res) if is_retryable(res.status()) => {
+                retry_after(res).unwrap_or_else(|| self.backoff(attempt))
+            }
+            Err(e) if e.is_timeout() || e.is_body() => self.backoff(attempt),
+            _ => return None,
+        };
+        Some(delay.min(Duration::from_millis(self.max_delay_ms)))
+    }
+}
+
+pub struct Http {
+    client: reqwest::Client,
+    policy: RetryPolicy,
+    retries: AtomicU32,
+    usage: Mutex<Vec<UsageEntry>>,
+}
+
+impl Http {
+    pub fn new(client: reqwest::Client, policy: RetryPolicy) -> Http {
+        Http {
+            client,
+            policy,
+            retries: AtomicU32::new(0),
+            usage: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client
+            .post(url)
+            .timeout(Duration::from_secs(self.policy.timeout_secs.max(1)))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn record_usage(&self, entry: UsageEntry) {
+        if let Ok(mut usage) = self.usage.lock() {
+            usage.push(entry);
+        }
+    }
+
+    pub async fn tagged<T>(&self, step: UsageStep, call: impl Future<Output = T>) -> T {
+        let output = call.await;
+        if let Ok(mut usage) = self.usage.lock() {
+            for entry in usage.iter_mut().filter(|e| e.step.is_none()) {
+                entry.step = Some(step);
+            }
+        }
+        output
+    }
+
+    pub fn take_usage(&self) -> Vec<UsageEntry> {
+        self.usage
+            .lock()
+            .map(|mut usage| std::mem::take(&mut *usage))
+            .unwrap_or_default()
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let max_attempts = self.policy.max_attempts.max(1);
+        let mut request = request;
+        let mut attempt = 1;
+
+        loop {
+            let next = if attempt < max_attempts {
+                request.try_clone()
+            } else {
+                None
+            };
+            let outcome = match request.send().await {
+                Ok(res) if !is_retryable(res.status()) => buffer(res).await,
+                outcome => outcome,
+            };
+
+            let (Some(next), Some(delay)) = (next, self.policy.delay(attempt, &outcome)) else {
+                return outcome;
+            };
+            let reason = match &outcome {
+                Ok(res) => res.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            println!(
+                "Tentativo {} di {} fallito ({}), nuovo tentativo tra {} ms",
+                attempt,
+                max_attempts,
+                reason,
+                delay.as_millis()
+            );
+
+            sleep(delay).await;
+            request = next;
+            attempt += 1;
+            self.retries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Reads the body inside the retry loop, so a connection dropped mid-response is retried too.
+async fn buffer(res: Response) -> Result<Response, reqwest::Error> {
+    let (status, version, headers) = (res.status(), res.version(), res.headers().clone());
+    let mut buffered = http::Response::new(res.bytes().await?);
+    *buffered.status_mut() = status;
+    *buffered.version_mut() = version;
+    *buffered.headers_mut() = headers;
+    Ok(Response::from(buffered))
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+pub fn load_policy(settings: &Settings) -> RetryPolicy {
+    settings.get_as("retry").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, retry_after: Option<&str>) -> Response {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(value) = retry_after {
+            builder = builder.header(RETRY_AFTER, value);
+        }
+        Response::from(builder.body("").unwrap())
+    }
+
+    fn millis(range: std::ops::RangeInclusive<u64>, delay: Duration) -> bool {
+        range.contains(&(delay.as_millis() as u64))
+    }
+
+    #[test]
+    fn only_rate_limits_and_server_errors_are_retried() {
+        for status in [429, 500, 502, 503, 504] {
+            assert!(
+                is_retryable(StatusCode::from_u16(status).unwrap()),
+                "{}",
+                status
+            );
+        }
+        for status in [200, 400, 401, 403, 404, 422] {
+            assert!(
+                !is_retryable(StatusCode::from_u16(status).unwrap()),
+                "{}",
+                status
+            );
+        }
+
+        let policy = RetryPolicy::default();
+        assert!(policy.delay(1, &Ok(response(404, Some("5")))).is_none());
+        assert!(policy.delay(1, &Ok(response(200, None))).is_none());
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        assert_eq!(
+            retry_after(&response(429, Some("120"))),
+            Some(Duration::from_secs(120))
+        );
+
+        let in_30s = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let delay = retry_after(&response(503, Some(&in_30s))).unwrap();
+        assert!(millis(28_000..=30_000, delay), "{:?}", delay);
+
+        let past = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(30));
+        assert_eq!(
+            retry_after(&response(503, Some(&past))),
+            Some(Duration::ZERO)
+        );
+
+        assert_eq!(retry_after(&response(503, Some("domani"))), None);
+        assert_eq!(retry_after(&response(503, None)), None);
+    }
+
+    #[test]
+    fn server_delays_are_capped_at_the_maximum() {
+        let policy = RetryPolicy {
+            max_delay_ms: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.delay(1, &Ok(response(429, Some("3600")))),
+            Some(Duration::from_millis(10_000))
+        );
+        assert_eq!(
+            policy.delay(1, &Ok(response(429, Some("2")))),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        for _ in 0..20 {
+            assert!(millis(500..=1_000, policy.backoff(1)));
+            assert!(millis(2_000..=4_000, policy.backoff(3)));
+            assert!(millis(30_000..=60_000, policy.backoff(8)));
+            assert!(millis(30_000..=60_000, policy.backoff(100)));
+        }
+
+        let delay = policy.delay(2, &Ok(response(503, None))).unwrap();
+        assert!(millis(1_000..=2_000, delay), "{:?}", delay);
+    }
+}
@@ -0,0 +1,50 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+pub const APP_IDENTIFIER: &str = "com.silas.maggus";
+
+#[derive(Clone, Default)]
+pub struct Settings {
+    values: HashMap<String, Value>,
+}
+
+impl Settings {
+    pub fn from_store(app: &AppHandle, name: &str) -> Settings {
+        let values = app
+            .store(name)
+            .map(|store| store.entries().into_iter().collect())
+            .unwrap_or_default();
+        Settings { values }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Settings, String> {
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Impossibile leggere {}: {}", path.display(), e))?;
+        let values = serde_json::from_str(&content)
+            .map_err(|e| format!("Parse errore {}: {}", path.display(), e))?;
+        Ok(Settings { values })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    pub fn get_as<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.values
+            .get(key)
+            .and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+}
+
+pub fn default_store_path(name: &str) -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER).join(name))
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::settings::Settings;
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum UsageKind {
+    Llm,
+    Ocr,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum UsageStep {
+    Primary,
+    LayoutRetry,
+    OcrFallback,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageEntry {
+    pub kind: UsageKind,
+    pub step: Option<UsageStep>,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub pages: u64,
+}
+
+impl UsageEntry {
+    pub fn llm(model: &str, usage: &serde_json::Value) -> UsageEntry {
+        let tokens = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        UsageEntry {
+            kind: UsageKind::Llm,
+            step: None,
+            model: model.to_string(),
+            prompt_tokens: tokens("prompt_tokens"),
+            completion_tokens: tokens("completion_tokens"),
+            pages: 0,
+        }
+    }
+
+    pub fn ocr(model: &str, pages: u64) -> UsageEntry {
+        UsageEntry {
+            kind: UsageKind::Ocr,
+            step: None,
+            model: model.to_string(),
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            pages,
+        }
+    }
+}
+
+pub struct StoredUsage {
+    pub day: String,
+    pub document_hash: String,
+    pub batch_id: Option<String>,
+    pub supplier: Option<String>,
+    pub entry: UsageEntry,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPrice {
+    pub model: String,
+    #[serde(default)]
+    pub input_per_million: f64,
+    #[serde(default)]
+    pub output_per_million: f64,
+    #[serde(default)]
+    pub per_thousand_pages: f64,
+}
+
+impl ModelPrice {
+    fn new(model: &str, input: f64, output: f64, pages: f64) -> ModelPrice {
+        ModelPrice {
+            model: model.to_string(),
+            input_per_million: input,
+            output_per_million: output,
+            per_thousand_pages: pages,
+        }
+    }
+
+    fn cost(&self, entry: &UsageEntry) -> f64 {
+        entry.prompt_tokens as f64 * self.input_per_million / 1_000_000.0
+            + entry.completion_tokens as f64 * self.output_per_million / 1_000_000.0
+            + entry.pages as f64 * self.per_thousand_pages / 1000.0
+    }
+}
+
+pub fn default_prices() -> Vec<ModelPrice> {
+    vec![
+        ModelPrice::new("mistral-large-latest", 2.0, 6.0, 0.0),
+        ModelPrice::new("mistral-ocr-latest", 0.0, 0.0, 1.0),
+        ModelPrice::new("gpt-4o-mini", 0.15, 0.6, 0.0),
+    ]
+}
+
+pub fn load_prices(settings: &Settings) -> Vec<ModelPrice> {
+    settings
+        .get_as("priceTable")
+        .filter(|p: &Vec<ModelPrice>| !p.is_empty())
+        .unwrap_or_else(default_prices)
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSummary {
+    pub key: String,
+    pub calls: usize,
+    pub documents: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub pages: u64,
+    pub cost: f64,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub total: UsageSummary,
+    pub by_day: Vec<UsageSummary>,
+    pub by_supplier: Vec<UsageSummary>,
+    pub by_batch: Vec<UsageSummary>,
+    pub by_step: Vec<UsageSummary>,
+    pub unpriced_models: Vec<String>,
+}
+
+#[derive(Default)]
+struct Group<'a> {
+    summary: UsageSummary,
+    documents: HashSet<&'a str>,
+}
+
+impl<'a> Group<'a> {
+    fn add(&mut self, row: &'a StoredUsage, cost: f64) {
+        self.summary.calls += 1;
+        self.summary.prompt_tokens += row.entry.prompt_tokens;
+        self.summary.completion_tokens += row.entry.completion_tokens;
+        self.summary.pages += row.entry.pages;
+        self.summary.cost += cost;
+        self.documents.insert(&row.document_hash);
+    }
+
+    fn finish(mut self, key: String) -> UsageSummary {
+        self.summary.key = key;
+        self.summary.documents = self.documents.len();
+        self.summary
+    }
+}
+
+fn step_key(entry: &UsageEntry) -> String {
+    let kind = match entry.kind {
+        UsageKind::Llm => "llm",
+        UsageKind::Ocr => "ocr",
+    };
+    let step = match entry.step {
+        Some(UsageStep::Primary) | None => "primary",
+        Some(UsageStep::LayoutRetry) => "layoutRetry",
+        Some(UsageStep::OcrFallback) => "ocrFallback",
+    };
+    format!("{}:{}", step, kind)
+}
+
+pub fn build_report(rows: &[StoredUsage], prices: &[ModelPrice]) -> UsageReport {
+    let mut total = Group::default();
+    let mut groups: [BTreeMap<String, Group>; 4] = Default::default();
+    let mut unpriced = BTreeSet::new();
+
+    for row in rows {
+        let price = prices
+            .iter()
+            .find(|p| p.model.eq_ignore_ascii_case(&row.entry.model));
+        let cost = match price {
+            Some(price) => price.cost(&row.entry),
+            None => {
+                unpriced.insert(row.entry.model.clone());
+                0.0
+            }
+        };
+
+        total.add(row, cost);
+        let keys = [
+            row.day.clone(),
+            row.supplier.clone().unwrap_or_else(|| "-".to_string()),
+            row.batch_id.clone().unwrap_or_else(|| "-".to_string()),
+            step_key(&row.entry),
+        ];
+        for (group, key) in groups.iter_mut().zip(keys) {
+            group.entry(key).or_default().add(row, cost);
+        }
+    }
+
+    let [by_day, by_supplier, by_batch, by_step] =
+        groups.map(|g| g.into_iter().map(|(k, g)| g.finish(k)).collect());
+
+    UsageReport {
+        total: total.finish("totale".to_string()),
+        by_day,
+        by_supplier,
+        by_batch,
+        by_step,
+        unpriced_models: unpriced.into_iter().collect(),
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LockInfo {
+    pub lock_file: PathBuf,
+    pub owner: Option<String>,
+    pub host: Option<String>,
+    pub since: Option<String>,
+}
+
+impl LockInfo {
+    pub fn message(&self) -> String {
+        let mut msg = match &self.owner {
+            Some(owner) => format!("Il file è aperto da {}", owner),
+            None => "Il file è aperto in un altro programma".to_string(),
+        };
+        if let Some(host) = &self.host {
+            msg.push_str(&format!(" su {}", host));
+        }
+        if let Some(since) = &self.since {
+            msg.push_str(&format!(" (dal {})", since));
+        }
+        format!(
+            "{}. Chiudilo e riprova; se nessuno lo sta usando, elimina {}.",
+            msg,
+            self.lock_file.display()
+        )
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+fn parse_office_owner(bytes: &[u8]) -> Option<String> {
+    let len = *bytes.first()? as usize;
+    let name: String = bytes.get(1..1 + len)?.iter().map(|&b| b as char).collect();
+    non_empty(Some(&name))
+}
+
+fn parse_libreoffice_owner(content: &str) -> (Option<String>, Option<String>, Option<String>) {
+    // Name,SysUser,Host,DateTime,UserURL;
+    let fields: Vec<&str> = content.trim().trim_end_matches(';').split(',').collect();
+    let owner = non_empty(fields.first().copied()).or_else(|| non_empty(fields.get(1).copied()));
+    (
+        owner,
+        non_empty(fields.get(2).copied()),
+        non_empty(fields.get(3).copied()),
+    )
+}
+
+pub fn find_lock(path: &Path) -> Option<LockInfo> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path.file_name()?.to_string_lossy().to_string();
+
+    let mut office_candidates = vec![dir.join(format!("~${}", name))];
+    let stem_len = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().chars().count())
+        .unwrap_or(0);
+    if stem_len >= 8 {
+        let shortened: String = name.chars().skip(2).collect();
+        office_candidates.push(dir.join(format!("~${}", shortened)));
+    }
+
+    for lock_file in office_candidates {
+        if let Ok(bytes) = fs::read(&lock_file) {
+            return Some(LockInfo {
+                owner: parse_office_owner(&bytes),
+                lock_file,
+                ..Default::default()
+            });
+        }
+    }
+
+    let lock_file = dir.join(format!(".~lock.{}#", name));
+    if let Ok(bytes) = fs::read(&lock_file) {
+        let (owner, host, since) = parse_libreoffice_owner(&String::from_utf8_lossy(&bytes));
+        return Some(LockInfo {
+            lock_file,
+            owner,
+            host,
+            since,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn office_owner_file(name: &str) -> Vec<u8> {
+        let mut bytes = vec![name.len() as u8];
+        bytes.extend(name.bytes());
+        bytes.resize(54, b' ');
+        bytes.extend((name.len() as u16).to_le_bytes());
+        bytes.extend(name.encode_utf16().flat_map(|c| c.to_le_bytes()));
+        bytes.resize(162, 0);
+        bytes
+    }
+
+    #[test]
+    fn office_owner_is_read_from_the_length_prefixed_name() {
+        assert_eq!(
+            parse_office_owner(&office_owner_file("Mario Rossi")).as_deref(),
+            Some("Mario Rossi")
+        );
+        assert_eq!(parse_office_owner(&office_owner_file("")), None);
+        assert_eq!(parse_office_owner(&[]), None);
+    }
+
+    #[test]
+    fn libreoffice_lock_line_maps_name_host_and_date() {
+        let line = "Mario Rossi,mrossi,UFFICIO-PC,18.10.2026 09:14,file:///C:/Users/mrossi/AppData/Roaming/LibreOffice/4;";
+        assert_eq!(
+            parse_libreoffice_owner(line),
+            (
+                Some("Mario Rossi".to_string()),
+                Some("UFFICIO-PC".to_string()),
+                Some("18.10.2026 09:14".to_string()),
+            )
+        );
+    }
+
+    #[test]
+    fn libreoffice_lock_without_name_falls_back_to_system_user() {
+        let line =
+            ",mrossi,ufficio-linux,18.10.2026 09:14,file:///home/mrossi/.config/libreoffice/4;";
+        let (owner, host, _) = parse_libreoffice_owner(line);
+        assert_eq!(owner.as_deref(), Some("mrossi"));
+        assert_eq!(host.as_deref(), Some("ufficio-linux"));
+    }
+
+    #[test]
+    fn lock_files_are_found_next_to_the_workbook() {
+        let dir = std::env::temp_dir().join(format!("maggus-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let workbook = dir.join("Ordini fornitori.xlsx");
+        assert!(find_lock(&workbook).is_none());
+
+        let lock_file = dir.join(".~lock.Ordini fornitori.xlsx#");
+        fs::write(&lock_file, ",mrossi,UFFICIO-PC,18.10.2026 09:14,file:///x;").unwrap();
+        let lock = find_lock(&workbook).unwrap();
+        assert_eq!(lock.owner.as_deref(), Some("mrossi"));
+        assert_eq!(lock.host.as_deref(), Some("UFFICIO-PC"));
+        fs::remove_file(&lock_file).unwrap();
+
+        fs::write(
+            dir.join("~$dini fornitori.xlsx"),
+            office_owner_file("Anna Bianchi"),
+        )
+        .unwrap();
+        let lock = find_lock(&workbook).unwrap();
+        assert_eq!(lock.owner.as_deref(), Some("Anna Bianchi"));
+        assert!(lock.message().contains("Anna Bianchi"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
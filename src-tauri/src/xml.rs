@@ -0,0 +1,62 @@
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+pub enum Node<'a> {
+    Start(&'a [String]),
+    Text(&'a [String], String),
+}
+
+pub fn decode(bytes: Vec<u8>) -> String {
+    let bytes = match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        Some(rest) => rest.to_vec(),
+        None => bytes,
+    };
+    let declaration = String::from_utf8_lossy(&bytes[..bytes.len().min(100)]).to_lowercase();
+
+    if declaration.contains("8859-1") || declaration.contains("1252") {
+        bytes.iter().map(|&b| b as char).collect()
+    } else {
+        String::from_utf8_lossy(&bytes).to_string()
+    }
+}
+
+pub fn walk(xml: &str, mut visit: impl FnMut(Node)) -> Result<Option<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut path: Vec<String> = Vec::new();
+    let mut root = None;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("XML non valido: {}", e))?;
+        match event {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                root.get_or_insert_with(|| name.clone());
+                path.push(name);
+                visit(Node::Start(&path));
+            }
+            Event::Empty(e) => {
+                root.get_or_insert_with(|| {
+                    String::from_utf8_lossy(e.local_name().as_ref()).to_string()
+                });
+            }
+            Event::End(_) => {
+                path.pop();
+            }
+            Event::Text(t) => {
+                let text = t.unescape().map_err(|e| format!("XML non valido: {}", e))?;
+                visit(Node::Text(&path, text.to_string()));
+            }
+            Event::CData(t) => {
+                visit(Node::Text(&path, String::from_utf8_lossy(&t).to_string()));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(root)
+}
@@ -0,0 +1,138 @@
+export const EXTRACTION_SCHEMA_VERSION = 8;
+
+export interface OrderProduct {
+  produkt: string;
+  artikelnummer?: string | null;
+  einheit?: string | null;
+  menge?: number | null;
+  waehrung?: string | null;
+  preis?: number | null;
+}
+
+export interface InvoiceProduct {
+  produkt: string;
+  artikelnummer?: string | null;
+  einheit?: string | null;
+  gelieferteMenge?: number | null;
+  preis?: number | null;
+  nummerAuftrag?: string | null;
+}
+
+export interface DeliveryProduct {
+  produkt: string;
+  artikelnummer?: string | null;
+  einheit?: string | null;
+  gelieferteMenge?: number | null;
+  nummerAuftrag?: string | null;
+}
+
+export type DocumentKind =
+  | "auftrag"
+  | "rechnung"
+  | "gutschrift"
+  | "lieferschein"
+  | "proforma";
+
+export interface Classification {
+  kind: DocumentKind;
+  confidence: number;
+}
+
+export interface AppliedCorrection {
+  rule: string;
+  field: "produkt" | "einheit" | "waehrung" | "lieferant" | "kunde";
+  before: string;
+  after: string;
+  explanation: string;
+}
+
+export interface CatalogMatch {
+  produkt: string;
+  before: string;
+  einheit?: string | null;
+  via: "code" | "name" | "synonym";
+  explanation: string;
+}
+
+export interface DocumentStatus {
+  hash: string;
+  cached: boolean;
+  exportedAt?: string | null;
+  workbook?: string | null;
+  // Rows as last exported, with the user's edits.
+  correctedRows?: object[];
+}
+
+export interface OrderExtraction {
+  schemaVersion: number;
+  docType: "auftrag";
+  produkte: OrderProduct[];
+  nummerAuftrag?: string | null;
+  datumAuftrag?: string | null;
+  lieferant?: string | null;
+  kunde?: string | null;
+  document?: DocumentStatus | null;
+  retries?: number;
+  mismatches?: string[];
+  classification?: Classification | null;
+  corrections?: AppliedCorrection[];
+  catalog?: CatalogMatch[];
+}
+
+export interface InvoiceExtraction {
+  schemaVersion: number;
+  docType: "rechnung";
+  produkte: InvoiceProduct[];
+  nummerRechnung?: string | null;
+  datumRechnung?: string | null;
+  lieferant?: string | null;
+  kunde?: string | null;
+  auftraege?: string[];
+  document?: DocumentStatus | null;
+  retries?: number;
+  mismatches?: string[];
+  classification?: Classification | null;
+  corrections?: AppliedCorrection[];
+  catalog?: CatalogMatch[];
+}
+
+export interface DeliveryNoteExtraction {
+  schemaVersion: number;
+  docType: "lieferschein";
+  produkte: DeliveryProduct[];
+  nummerDdt?: string | null;
+  datumDdt?: string | null;
+  lieferant?: string | null;
+  kunde?: string | null;
+  auftraege?: string[];
+  document?: DocumentStatus | null;
+  retries?: number;
+  mismatches?: string[];
+  classification?: Classification | null;
+  corrections?: AppliedCorrection[];
+  catalog?: CatalogMatch[];
+}
+
+export interface CreditNoteExtraction {
+  schemaVersion: number;
+  docType: "gutschrift";
+  produkte: InvoiceProduct[];
+  nummerGutschrift?: string | null;
+  datumGutschrift?: string | null;
+  lieferant?: string | null;
+  kunde?: string | null;
+  auftraege?: string[];
+  rechnungen?: string[];
+  document?: DocumentStatus | null;
+  retries?: number;
+  mismatches?: string[];
+  classification?: Classification | null;
+  corrections?: AppliedCorrection[];
+  catalog?: CatalogMatch[];
+}
+
+export type AnalysisResult =
+  | OrderExtraction
+  | InvoiceExtraction
+  | DeliveryNoteExtraction
+  | CreditNoteExtraction;
@@ -4,9 +4,18 @@ import { join } from "@tauri-apps/api/path";
 import Handsontable from "handsontable";
 import { openPath } from "@tauri-apps/plugin-opener";
 import { invoke } from "@tauri-apps/api/core";
-import { chunk } from "lodash";
 import { Store } from "@tauri-apps/plugin-store";
 import { listen } from "@tauri-apps/api/event";
+import {
+  AnalysisResult,
+  AppliedCorrection,
+  CatalogMatch,
+  DeliveryProduct,
+  DocumentStatus,
+  EXTRACTION_SCHEMA_VERSION,
+  InvoiceProduct,
+  OrderProduct,
+} from "./extraction";
 
 import "handsontable/styles/handsontable.min.css";
 import "handsontable/styles/ht-theme-main.min.css";
@@ -14,7 +23,7 @@ interface PdfDataRow {
   id: number;
   pdfName: string;
   fullPath: string;
-  docType: "auftrag" | "rechnung";
+  docType: "auftrag" | "rechnung" | "lieferschein" | "gutschrift";
   confirmed: boolean;
   warnings?: boolean;
 
@@ -32,19 +41,192 @@ interface PdfDataRow {
   datumRechnung?: string | null;
   nummerRechnung?: string | null;
   gelieferteMenge?: number | null;
+  preisRechnung?: number | null;
+
+  datumDdt?: string | null;
+  nummerDdt?: string | null;
+  mengeDdt?: number | null;
+
+  nummerGutschrift?: string | null;
+  mengeGutschrift?: number | null;
 
   anmerkungen?: string | null;
+  documentHash?: string | null;
+  correctionNote?: string | null;
+  artikelnummer?: string | null;
+  einheit?: string | null;
 }
-interface AiProduct {
-  produkt?: string | null;
-  menge?: number | null;
-  waehrung?: string | null;
-  preis?: number | null;
-  gelieferteMenge?: number | null;
-}
+type AiProduct = Partial<OrderProduct & InvoiceProduct & DeliveryProduct>;
 interface AiResponse {
+  schemaVersion?: number;
+  docType?: AnalysisResult["docType"];
   nummerRechnung?: string | null;
+  datumRechnung?: string | null;
+  nummerDdt?: string | null;
+  datumDdt?: string | null;
+  nummerGutschrift?: string | null;
+  datumGutschrift?: string | null;
+  nummerAuftrag?: string | null;
+  datumAuftrag?: string | null;
+  lieferant?: string | null;
+  kunde?: string | null;
+  auftraege?: string[];
   produkte?: AiProduct[];
+  document?: DocumentStatus | null;
+  mismatches?: string[];
+  corrections?: AppliedCorrection[];
+  catalog?: CatalogMatch[];
+}
+type BatchStatus =
+  | "queued"
+  | "extracting"
+  | "ocr"
+  | "llm"
+  | "done"
+  | "failed"
+  | "cancelled";
+interface BatchStatusEvent {
+  batchId: string;
+  index: number;
+  path: string;
+  status: BatchStatus;
+  result: AnalysisResult | null;
+  error: string | null;
+}
+interface FileMetadata {
+  pdfName: string;
+  docType: "auftrag" | "rechnung" | "lieferschein" | "gutschrift";
+  warnings: boolean;
+  kunde: string | null;
+  lieferant: string | null;
+  datumAuftrag: string | null;
+  nummerAuftrag: string | null;
+  datumRechnung: string | null;
+  datumDdt: string | null;
+}
+interface FilenamePattern {
+  name: string;
+  docType: "auftrag" | "rechnung" | "lieferschein" | "gutschrift";
+  pattern: string;
+  dateFormat?: string | null;
+  extensions?: string[];
+}
+interface OcrSettings {
+  backend?: "mistral" | "tesseract";
+  languages?: string;
+  tesseractPath?: string;
+  pdftoppmPath?: string;
+  dpi?: number;
+}
+interface CellChange {
+  field: string;
+  column: string;
+  oldValue: string;
+  newValue: string;
+}
+interface ReconcileSettings {
+  enabled?: boolean;
+  quantityTolerance?: number;
+  priceTolerancePercent?: number;
+  writeNotes?: boolean;
+}
+interface Finding {
+  kind:
+    | "underDelivery"
+    | "overDelivery"
+    | "missingInvoice"
+    | "missingOrder"
+    | "priceDeviation";
+  nummerAuftrag: string;
+  produkt: string;
+  row?: number | null;
+  note: string;
+}
+interface ProductMatchSettings {
+  enabled?: boolean;
+  threshold?: number;
+  ambiguityMargin?: number;
+}
+interface MatchCandidate {
+  row: number;
+  produkt: string;
+  score: number;
+}
+interface AmbiguousMatch {
+  row: Partial<PdfDataRow>;
+  candidates: MatchCandidate[];
+}
+interface CorrectionRule {
+  name: string;
+  field?: AppliedCorrection["field"];
+  mode?: "exact" | "normalized" | "regex";
+  pattern: string;
+  replacement: string;
+  lieferant?: string | null;
+  kunde?: string | null;
+  priority?: number;
+  enabled?: boolean;
+}
+interface CatalogEntry {
+  name: string;
+  unit?: string | null;
+  codes?: { lieferant: string; code: string }[];
+  synonyms?: string[];
+}
+interface ExportDiff {
+  headerRow: number;
+  updates: {
+    row: number;
+    nummerAuftrag: string;
+    produkt: string;
+    changes: CellChange[];
+  }[];
+  insertions: { beforeRow: number; row: Partial<PdfDataRow> }[];
+  notes: {
+    row: number;
+    nummerAuftrag: string;
+    produkt: string;
+    change: CellChange;
+  }[];
+  unmatchable: Partial<PdfDataRow>[];
+  ambiguous: AmbiguousMatch[];
+  fuzzyMatches: {
+    nummerAuftrag: string;
+    produkt: string;
+    candidate: MatchCandidate;
+  }[];
+  reconciliation?: { checked: number; findings: Finding[] } | null;
+  unchanged: number;
+  warnings: string[];
+}
+interface ExportReport {
+  message: string;
+  dryRun: boolean;
+  filePath: string | null;
+  diff: ExportDiff | null;
+}
+interface JournalEntry {
+  id: string;
+  timestamp: string;
+  kind: "export" | "restore";
+  workbook: string;
+  backup: string;
+  updated: number;
+  inserted: number;
+  restoredFrom?: string | null;
+  restoredAt?: string | null;
+}
+interface LlmProfile {
+  name: string;
+  provider: "mistral" | "openAi" | "local";
+  baseUrl?: string | null;
+  model?: string | null;
+}
+type ColumnRef = { letter: string } | { header: string };
+interface ExcelMapping {
+  name: string;
+  headerAnchor: { text: string; column?: string | null };
+  columns: Record<string, ColumnRef | null>;
 }
 
 let selectedPdfPaths: string[] = [];
@@ -55,6 +237,10 @@ let store: Store | null = null;
 
 let isProcessing = false;
 
+let llmProfiles: LlmProfile[] = [];
+
+let excelMappings: ExcelMapping[] = [];
+
 document.addEventListener("DOMContentLoaded", async () => {
   const exportBtn = document.getElementById("export-excel-btn");
   if (exportBtn) {
@@ -79,6 +265,14 @@ document.addEventListener("DOMContentLoaded", async () => {
 
   setupProgressBar();
 
+  loadCorrectionRuleErrors().then((errors) => {
+    if (errors.length)
+      showToast(
+        `Regole di correzione ignorate: ${errors.join("; ")}`,
+        "error"
+      );
+  });
+
   const startProcessBtn = document.querySelector(
     "#start-process-btn"
   ) as HTMLButtonElement;
@@ -160,6 +354,39 @@ document.addEventListener("DOMContentLoaded", async () => {
   const excelPathInput = document.getElementById(
     "setting-excel-path"
   ) as HTMLInputElement;
+  const pdfBackendSelect = document.getElementById(
+    "setting-pdf-backend"
+  ) as HTMLSelectElement;
+  const pdftotextFallbackInput = document.getElementById(
+    "setting-pdftotext-fallback"
+  ) as HTMLInputElement;
+  const ocrBackendSelect = document.getElementById(
+    "setting-ocr-backend"
+  ) as HTMLSelectElement;
+  const ocrLanguagesInput = document.getElementById(
+    "setting-ocr-languages"
+  ) as HTMLInputElement;
+  const exportPreviewInput = document.getElementById(
+    "setting-export-preview"
+  ) as HTMLInputElement;
+  const partialInvoicesSelect = document.getElementById(
+    "setting-partial-invoices"
+  ) as HTMLSelectElement;
+  const reconcileEnabledInput = document.getElementById(
+    "setting-reconcile-enabled"
+  ) as HTMLInputElement;
+  const reconcileQtyInput = document.getElementById(
+    "setting-reconcile-qty"
+  ) as HTMLInputElement;
+  const reconcilePriceInput = document.getElementById(
+    "setting-reconcile-price"
+  ) as HTMLInputElement;
+  const productMatchEnabledInput = document.getElementById(
+    "setting-product-match-enabled"
+  ) as HTMLInputElement;
+  const productMatchThresholdInput = document.getElementById(
+    "setting-product-match-threshold"
+  ) as HTMLInputElement;
 
   const toggleApiKeyBtn = document.getElementById("toggle-api-key-btn");
 
@@ -202,7 +429,19 @@ document.addEventListener("DOMContentLoaded", async () => {
     });
 
   settingsBtn?.addEventListener("click", async () => {
-    const [apiKey, pdfPath, excelPath, theme] = await Promise.all([
+    const [
+      apiKey,
+      pdfPath,
+      excelPath,
+      theme,
+      pdfBackend,
+      pdftotextFallback,
+      ocrSettings,
+      exportPreview,
+      partialInvoices,
+      reconcileSettings,
+      productMatchSettings,
+    ] = await Promise.all([
       invoke<string>("get_api_key").catch((err) => {
         console.warn(
           "Impossibile caricare la chiave API (forse al primo avvio):",
@@ -213,14 +452,54 @@ document.addEventListener("DOMContentLoaded", async () => {
       store?.get("defaultPdfPath").catch(() => null),
       store?.get("defaultExcelPath").catch(() => null),
       store?.get("defaultTheme").catch(() => null),
+      store?.get("pdfTextBackend").catch(() => null),
+      store?.get("pdftotextFallback").catch(() => null),
+      store?.get<OcrSettings>("ocr").catch(() => null),
+      store?.get("exportPreview").catch(() => null),
+      store?.get("partialInvoices").catch(() => null),
+      store?.get<ReconcileSettings>("reconciliation").catch(() => null),
+      store?.get<ProductMatchSettings>("productMatching").catch(() => null),
     ]);
 
     if (apiKeyInput) apiKeyInput.value = apiKey || "";
     if (pdfPathInput) pdfPathInput.value = (pdfPath as string) || "";
     if (excelPathInput) excelPathInput.value = (excelPath as string) || "";
     if (theme) themeToggle.checked = theme === "light";
+    if (pdfBackendSelect)
+      pdfBackendSelect.value = (pdfBackend as string) || "native";
+    if (pdftotextFallbackInput)
+      pdftotextFallbackInput.checked = pdftotextFallback !== false;
+    if (ocrBackendSelect)
+      ocrBackendSelect.value = ocrSettings?.backend || "mistral";
+    if (ocrLanguagesInput) ocrLanguagesInput.value = ocrSettings?.languages || "";
+    if (exportPreviewInput)
+      exportPreviewInput.checked = exportPreview !== false;
+    if (partialInvoicesSelect)
+      partialInvoicesSelect.value = (partialInvoices as string) || "accumulate";
+    if (reconcileEnabledInput)
+      reconcileEnabledInput.checked = reconcileSettings?.enabled !== false;
+    if (reconcileQtyInput)
+      reconcileQtyInput.value = String(
+        reconcileSettings?.quantityTolerance ?? 0
+      );
+    if (reconcilePriceInput)
+      reconcilePriceInput.value = String(
+        reconcileSettings?.priceTolerancePercent ?? 2
+      );
+    if (productMatchEnabledInput)
+      productMatchEnabledInput.checked = productMatchSettings?.enabled !== false;
+    if (productMatchThresholdInput)
+      productMatchThresholdInput.value = String(
+        Math.round((productMatchSettings?.threshold ?? 0.85) * 100)
+      );
 
     loadAndRenderCorrections();
+    loadExportJournal();
+    loadLlmProfiles();
+    loadExcelMappings();
+    loadFilenamePatterns();
+    loadCorrectionRules();
+    loadProductCatalog();
 
     settingsModal!.style.display = "flex";
   });
@@ -232,9 +511,42 @@ document.addEventListener("DOMContentLoaded", async () => {
   saveSettingsBtn?.addEventListener("click", async () => {
     try {
       await invoke("save_api_key", { key: apiKeyInput.value });
+      await saveLlmProfile();
+      await saveExcelMapping();
+      await invoke("save_filename_patterns", {
+        patterns: readFilenamePatterns(),
+      });
+      await invoke("save_correction_rules", { rules: readCorrectionRules() });
+      renderCorrectionRuleErrors(await loadCorrectionRuleErrors());
+      await invoke("save_product_catalog", { entries: readProductCatalog() });
 
       await store?.set("defaultPdfPath", pdfPathInput.value);
       await store?.set("defaultExcelPath", excelPathInput.value);
+      await store?.set("pdfTextBackend", pdfBackendSelect.value);
+      await store?.set("pdftotextFallback", pdftotextFallbackInput.checked);
+      await store?.set("exportPreview", exportPreviewInput.checked);
+      await store?.set("partialInvoices", partialInvoicesSelect.value);
+      const reconcileSettings =
+        (await store?.get<ReconcileSettings>("reconciliation")) || {};
+      await store?.set("reconciliation", {
+        ...reconcileSettings,
+        enabled: reconcileEnabledInput.checked,
+        quantityTolerance: Number(reconcileQtyInput.value) || 0,
+        priceTolerancePercent: Number(reconcilePriceInput.value) || 0,
+      });
+      const productMatchSettings =
+        (await store?.get<ProductMatchSettings>("productMatching")) || {};
+      await store?.set("productMatching", {
+        ...productMatchSettings,
+        enabled: productMatchEnabledInput.checked,
+        threshold: (Number(productMatchThresholdInput.value) || 85) / 100,
+      });
+      const ocrSettings = (await store?.get<OcrSettings>("ocr")) || {};
+      await store?.set("ocr", {
+        ...ocrSettings,
+        backend: ocrBackendSelect.value,
+        languages: ocrLanguagesInput.value.trim() || undefined,
+      });
       const newTheme = themeToggle.checked ? "light" : "dark";
       await store?.set("defaultTheme", newTheme);
 
@@ -274,7 +586,7 @@ document.addEventListener("DOMContentLoaded", async () => {
       Array.isArray(droppedPaths) &&
       droppedPaths.length > 0
     ) {
-      const pdfs = droppedPaths.filter((p) => p.toLowerCase().endsWith(".pdf"));
+      const pdfs = droppedPaths.filter(isSupportedDocument);
 
       if (pdfs.length === 0) {
         showToast("Nessun file PDF rilevato.", "error");
@@ -313,13 +625,19 @@ if (themeToggle) {
   toggleTheme();
 }
 
+const DOCUMENT_EXTENSION = /\.(pdf|xml\.p7m|xml|p7m)$/i;
+
+function isSupportedDocument(path: string) {
+  return DOCUMENT_EXTENSION.test(path);
+}
+
 async function loadPdfsFromDirectory(path: string) {
   try {
     const entries = await readDir(path);
 
     const pdfEntries = entries.filter(
       (entry) =>
-        entry.name?.toLowerCase().endsWith(".pdf") && !entry.isDirectory
+        !!entry.name && isSupportedDocument(entry.name) && !entry.isDirectory
     );
 
     selectedPdfPaths = await Promise.all(
@@ -346,8 +664,8 @@ async function handleSelectFiles() {
     multiple: true,
     filters: [
       {
-        name: "PDF",
-        extensions: ["pdf"],
+        name: "PDF / FatturaPA",
+        extensions: ["pdf", "xml", "p7m"],
       },
     ],
   });
@@ -443,66 +761,84 @@ async function handleReseachStart() {
     let completedCount = 0;
     setProgress(0, totalTasks);
 
-    const chunkedData = chunk(data, 5);
+    const documents = data
+      .map((row, index) => ({ index, path: row.fullPath, docType: row.docType }))
+      .filter((d) => d.path);
     const aiResults: {
-      index: number;
       row: PdfDataRow;
-      docType: "auftrag" | "rechnung";
+      docType: "auftrag" | "rechnung" | "lieferschein" | "gutschrift";
       result: AiResponse;
+      error?: string;
     }[] = [];
 
-    for (const dataChunk of chunkedData) {
-      const chunkResults = await Promise.all(
-        dataChunk.map(async (row, index) => {
-          if (!row.fullPath) return null;
-
-          try {
-            const result = await invoke<AiResponse>("analyze_document", {
-              path: row.fullPath,
-              docType: row.docType,
-            });
-
-            const docType = row.docType;
-
-            completedCount++;
-            setProgress(completedCount, totalTasks);
-            return { index, row, docType, result };
-          } catch (err) {
-            console.error(err);
-            completedCount++;
-            setProgress(completedCount, totalTasks);
-            hot!.setDataAtRowProp(index, "anmerkungen", String(err));
-            return {
-              index,
-              row,
-              docType: row.docType,
-              result: {} as AiResponse,
-            };
-          }
-        })
-      );
-      aiResults.push(
-        ...chunkResults.filter(
-          (
-            item
-          ): item is {
-            index: number;
-            row: PdfDataRow;
-            docType: "auftrag" | "rechnung";
-            result: AiResponse;
-          } => item !== null
-        )
-      );
-    }
+    const outcomes = await runBatch(
+      documents.map(({ path, docType }) => ({ path, docType })),
+      (event) => {
+        const index = documents[event.index]?.index;
+        if (index === undefined) return;
+        if (event.status === "done" || event.status === "failed") {
+          completedCount++;
+          setProgress(completedCount, totalTasks);
+        }
+        hot!.setDataAtRowProp(
+          index,
+          "anmerkungen",
+          event.error || BATCH_STATUS_LABELS[event.status]
+        );
+      }
+    );
+    setProgress(totalTasks, totalTasks);
+
+    outcomes.forEach((outcome, batchIndex) => {
+      const index = documents[batchIndex].index;
+      const row = data[index];
+      let result = {} as AiResponse;
+      let error = outcome.error ?? undefined;
+      if (outcome.result) {
+        try {
+          result = checkSchemaVersion(outcome.result);
+        } catch (err) {
+          console.error(err);
+          error = String(err);
+        }
+      } else if (outcome.status === "cancelled") {
+        error = BATCH_STATUS_LABELS.cancelled;
+      }
+      aiResults[index] = { row, docType: row.docType, result, error };
+    });
 
     const newTableData: PdfDataRow[] = [];
 
     data.forEach((_row, index) => {
       const aiResult = aiResults[index]?.result;
       const products = aiResult?.produkte;
-      const docType = aiResults[index]?.docType;
-
-      if (products && Array.isArray(products) && products.length > 0) {
+      const docType = aiResult?.docType ?? aiResults[index]?.docType;
+      const correctedRows = aiResult?.document?.correctedRows;
+
+      if (correctedRows?.length) {
+        correctedRows.forEach((stored, rowIndex) => {
+          const newRow: PdfDataRow = {
+            ..._row,
+            ...(stored as Partial<PdfDataRow>),
+          };
+          newRow.docType = docType ?? newRow.docType;
+          if (rowIndex > 0) {
+            newRow.pdfName = "";
+            newRow.fullPath = "";
+            newRow.confirmed = false;
+          } else {
+            newRow.warnings = true;
+            newRow.anmerkungen = [
+              exportedNote(aiResult),
+              "righe come esportate, con le correzioni",
+              ...(aiResult.mismatches ?? []),
+            ]
+              .filter(Boolean)
+              .join("; ");
+          }
+          newTableData.push(newRow);
+        });
+      } else if (products && Array.isArray(products) && products.length > 0) {
         products.forEach((prod, prodIndex) => {
           const newRow: PdfDataRow = { ..._row };
 
@@ -514,14 +850,44 @@ async function handleReseachStart() {
           }
 
           newRow.produkt = prod.produkt;
+          newRow.correctionNote = correctionNote(aiResult, prod.produkt);
+          newRow.artikelnummer = prod.artikelnummer ?? null;
+          newRow.einheit = productUnit(aiResult, prod);
+          newRow.docType = docType ?? newRow.docType;
+          newRow.documentHash = aiResult.document?.hash ?? null;
+          Object.assign(newRow, headerFields(newRow, aiResult));
 
           if (docType === "auftrag") {
             newRow.menge = prod.menge;
             newRow.waehrung = prod.waehrung;
             newRow.preis = prod.preis;
+          } else if (docType === "gutschrift") {
+            newRow.mengeGutschrift = creditedQuantity(prod);
+            newRow.nummerGutschrift = aiResult.nummerGutschrift;
+            newRow.nummerAuftrag = prod.nummerAuftrag || newRow.nummerAuftrag;
+            newRow.datumRechnung = null;
+          } else if (docType === "lieferschein") {
+            newRow.mengeDdt = prod.gelieferteMenge;
+            newRow.nummerDdt = aiResult.nummerDdt;
+            newRow.nummerAuftrag = prod.nummerAuftrag || newRow.nummerAuftrag;
+            newRow.datumDdt = aiResult.datumDdt || newRow.datumDdt;
           } else {
             newRow.gelieferteMenge = prod.gelieferteMenge;
+            newRow.preisRechnung = prod.preis;
             newRow.nummerRechnung = aiResult.nummerRechnung;
+            newRow.nummerAuftrag = prod.nummerAuftrag || newRow.nummerAuftrag;
+            newRow.datumRechnung = aiResult.datumRechnung || newRow.datumRechnung;
+          }
+
+          if (aiResult.document?.exportedAt && prodIndex === 0) {
+            newRow.warnings = true;
+            newRow.anmerkungen = exportedNote(aiResult);
+          }
+          if (aiResult.mismatches?.length && prodIndex === 0) {
+            newRow.warnings = true;
+            newRow.anmerkungen = [newRow.anmerkungen, ...aiResult.mismatches]
+              .filter(Boolean)
+              .join("; ");
           }
 
           newTableData.push(newRow);
@@ -531,6 +897,8 @@ async function handleReseachStart() {
         errorRow.warnings = true;
         if (!aiResults[index]) {
           errorRow.anmerkungen = "Errore: impossibile leggere il PDF.";
+        } else if (aiResults[index].error) {
+          errorRow.anmerkungen = aiResults[index].error;
         } else if (!aiResult) {
           errorRow.anmerkungen = "Errore: KI non ha risposto.";
         } else {
@@ -540,6 +908,16 @@ async function handleReseachStart() {
       }
     });
 
+    const alreadyExported = aiResults.filter(
+      (r) => r?.result.document?.exportedAt
+    ).length;
+    if (alreadyExported > 0) {
+      showToast(
+        `${alreadyExported} documenti sono già stati esportati in precedenza.`,
+        "info"
+      );
+    }
+
     hot.loadData(newTableData);
     hot.render();
     hot.updateSettings({
@@ -557,18 +935,142 @@ async function handleReseachStart() {
   }
 }
 
-function parseDateStrings(dateString: string) {
-  let date;
-  if (dateString && dateString.length === 8) {
-    const year = dateString.substring(0, 4);
-    const month = dateString.substring(4, 6);
-    const day = dateString.substring(6, 8);
-    date = `${day}.${month}.${year}`;
+function exportedNote(result: AiResponse): string {
+  const exportedAt = result.document?.exportedAt;
+  if (!exportedAt) return "";
+  return `Già esportato il ${new Date(exportedAt).toLocaleString()} in ${
+    result.document?.workbook || "-"
+  }`;
+}
+
+const BATCH_STATUS_LABELS: Record<BatchStatus, string> = {
+  queued: "In coda…",
+  extracting: "Estrazione testo…",
+  ocr: "OCR in corso…",
+  llm: "Analisi AI in corso…",
+  done: "",
+  failed: "Errore durante l'analisi.",
+  cancelled: "Analisi interrotta.",
+};
+
+async function runBatch(
+  documents: { path: string; docType: PdfDataRow["docType"] }[],
+  onStatus: (event: BatchStatusEvent) => void
+): Promise<BatchStatusEvent[]> {
+  const outcomes: BatchStatusEvent[] = [];
+  const pending: BatchStatusEvent[] = [];
+  let batchId: string | null = null;
+  let resolveFinished: (value?: void) => void = () => {};
+  const finished = new Promise<void>((resolve) => (resolveFinished = resolve));
+
+  const handle = (event: BatchStatusEvent) => {
+    if (["done", "failed", "cancelled"].includes(event.status)) {
+      outcomes[event.index] = event;
+    }
+    onStatus(event);
+  };
+
+  const unlistenStatus = await listen<BatchStatusEvent>(
+    "batch-status",
+    (event) => {
+      if (batchId === null) pending.push(event.payload);
+      else if (event.payload.batchId === batchId) handle(event.payload);
+    }
+  );
+  const finishedEarly = new Set<string>();
+  const unlistenFinished = await listen<{ batchId: string }>(
+    "batch-finished",
+    (event) => {
+      if (batchId === null) finishedEarly.add(event.payload.batchId);
+      else if (event.payload.batchId === batchId) resolveFinished();
+    }
+  );
+
+  const cancelBtn = document.querySelector(
+    "#cancel-process-btn"
+  ) as HTMLButtonElement | null;
+  const cancel = () => {
+    if (batchId) invoke("cancel_batch", { batchId });
+  };
+
+  try {
+    batchId = await invoke<string>("start_batch", { documents });
+    pending.filter((e) => e.batchId === batchId).forEach(handle);
+    if (finishedEarly.has(batchId)) resolveFinished();
+
+    if (cancelBtn) {
+      cancelBtn.hidden = false;
+      cancelBtn.addEventListener("click", cancel);
+    }
+    await finished;
+  } finally {
+    unlistenStatus();
+    unlistenFinished();
+    if (cancelBtn) {
+      cancelBtn.hidden = true;
+      cancelBtn.removeEventListener("click", cancel);
+    }
+  }
+
+  return documents.map(
+    (d, index) =>
+      outcomes[index] ?? {
+        batchId: batchId!,
+        index,
+        path: d.path,
+        status: "cancelled",
+        result: null,
+        error: null,
+      }
+  );
+}
+
+function headerFields(
+  row: PdfDataRow,
+  result: AiResponse
+): Partial<PdfDataRow> {
+  const fields: Partial<PdfDataRow> = {
+    lieferant: row.lieferant || result.lieferant || null,
+    kunde: row.kunde || result.kunde || null,
+  };
+  if (row.docType === "auftrag") {
+    fields.nummerAuftrag = row.nummerAuftrag || result.nummerAuftrag || null;
+    fields.datumAuftrag = row.datumAuftrag || result.datumAuftrag || null;
+  } else if (result.auftraege?.length === 1) {
+    fields.nummerAuftrag = row.nummerAuftrag || result.auftraege[0];
+  }
+  return fields;
+}
+
+function creditedQuantity(prod: AiProduct): number | null {
+  return prod.gelieferteMenge == null ? null : Math.abs(prod.gelieferteMenge);
+}
+
+function checkSchemaVersion(result: AnalysisResult): AiResponse {
+  if (result.schemaVersion !== EXTRACTION_SCHEMA_VERSION) {
+    throw new Error(
+      `Versione dello schema non supportata: ${result.schemaVersion} (attesa ${EXTRACTION_SCHEMA_VERSION})`
+    );
   }
-  return date || null;
+
+  return result;
+}
+
+async function analyzeDocument(
+  path: string,
+  docType: PdfDataRow["docType"],
+  refresh = false
+): Promise<AiResponse> {
+  const result = await invoke<AnalysisResult>("analyze_document", {
+    path,
+    docType,
+    refresh,
+  });
+
+  return checkSchemaVersion(result);
 }
 
-function updateFileUI() {
+async function updateFileUI() {
   if (!hot) return;
 
   const currentData = hot.getSourceData() as PdfDataRow[];
@@ -597,60 +1099,25 @@ function updateFileUI() {
     return;
   }
 
-  const newRows = newPaths
-    .map((path): PdfDataRow | null => {
-      try {
-        const lastSeparatorIndex = Math.max(
-          path.lastIndexOf("/"),
-          path.lastIndexOf("\\")
-        );
-        const fileName = path
-          .substring(lastSeparatorIndex + 1)
-          .replace(/\.pdf$/i, "")
-          .toUpperCase();
-
-        const isInvoice = fileName.startsWith("FT");
-        const docType = isInvoice ? "rechnung" : "auftrag";
-
-        let datumRechnung, datumAuftrag, nummerAuftrag, kunde, lieferant;
-
-        const parts = fileName.split("_");
-
-        if (isInvoice) {
-          datumRechnung = parseDateStrings(parts[2]?.split("-")[0]);
-          nummerAuftrag = parts[3] || null;
-          kunde = parts[2]?.split("-")[1] || null;
-          lieferant = parts[1] || null;
-        } else {
-          datumAuftrag = parseDateStrings(parts[1]);
-          nummerAuftrag = parts[0] || null;
-          kunde = parts[2]?.split("-")[1] || null;
-          lieferant = parts[2]?.split("-")[0] || null;
-        }
+  let metadata: FileMetadata[];
+  try {
+    metadata = await invoke<FileMetadata[]>("parse_filenames", {
+      paths: newPaths,
+    });
+  } catch (e) {
+    console.error("Errore durante l'analisi dei nomi file:", e);
+    showToast(`Errore: ${e}`, "error");
+    return;
+  }
 
-        const missingData = isInvoice
-          ? !datumRechnung || !nummerAuftrag || !kunde || !lieferant
-          : !datumAuftrag || !nummerAuftrag || !kunde || !lieferant;
-
-        return {
-          id: nextId++,
-          pdfName: fileName,
-          fullPath: path,
-          docType,
-          confirmed: false,
-          warnings: missingData,
-          kunde,
-          lieferant,
-          datumAuftrag: datumAuftrag || null,
-          nummerAuftrag,
-          datumRechnung: datumRechnung || null,
-        } as PdfDataRow;
-      } catch (e) {
-        console.error(`Errore durante l'analisi di ${path}:`, e);
-        return null;
-      }
+  const newRows = newPaths.map(
+    (path, index): PdfDataRow => ({
+      id: nextId++,
+      fullPath: path,
+      confirmed: false,
+      ...metadata[index],
     })
-    .filter((row): row is PdfDataRow => row !== null);
+  );
 
   hot.loadData([...currentData, ...newRows]);
 }
@@ -701,11 +1168,11 @@ function pdfNameRenderer(
 
 function ellipsisRenderer(
   this: Handsontable.Core,
-  _instance: Handsontable.Core,
+  instance: Handsontable.Core,
   td: HTMLTableCellElement,
-  _row: number,
+  row: number,
   _col: number,
-  _prop: string | number,
+  prop: string | number,
   value: Handsontable.CellValue,
   _cellProperties: Handsontable.CellProperties
 ) {
@@ -714,6 +1181,33 @@ function ellipsisRenderer(
   if (value !== null && value !== undefined) {
     td.title = String(value);
   }
+  if (prop === "produkt") {
+    const rowData = instance.getSourceDataAtRow(row) as PdfDataRow | undefined;
+    if (rowData?.correctionNote) {
+      td.title = [td.title, rowData.correctionNote].filter(Boolean).join("\n");
+    }
+  }
+}
+
+function correctionNote(
+  result: AiResponse,
+  produkt?: string | null
+): string | null {
+  const notes = [
+    ...(result.corrections ?? []).filter(
+      (c) => c.field !== "produkt" || c.after === produkt
+    ),
+    ...(result.catalog ?? []).filter((m) => m.produkt === produkt),
+  ].map((c) => c.explanation);
+  return notes.length ? notes.join("\n") : null;
+}
+
+function productUnit(result: AiResponse, product: AiProduct): string | null {
+  return (
+    product.einheit ??
+    result.catalog?.find((m) => m.produkt === product.produkt)?.einheit ??
+    null
+  );
 }
 
 function updateHeaderCheckboxState() {
@@ -836,12 +1330,20 @@ document.addEventListener("DOMContentLoaded", () => {
       "Cliente",
       "Casa Estera",
       "Prodotto",
+      "Cod. art.",
+      "U.M.",
       "kg/pz.",
       "Val.",
       "Prezzo kg/z.",
       "Data fattura Casa rapp.",
       "N° fattura Casa rapp.",
       "kg/pz.",
+      "Prezzo fattura",
+      "Data DDT",
+      "N° DDT",
+      "kg/pz. DDT",
+      "N° nota di credito",
+      "kg/pz. NC",
       "Note",
     ],
     className: "htEllipsis",
@@ -866,6 +1368,8 @@ document.addEventListener("DOMContentLoaded", () => {
       { data: "kunde", width: 120 },
       { data: "lieferant", width: 120 },
       { data: "produkt", width: 200 },
+      { data: "artikelnummer", width: 50 },
+      { data: "einheit", width: 30 },
       { data: "menge", type: "numeric", width: 40 },
       { data: "waehrung", width: 30 },
       {
@@ -884,6 +1388,24 @@ document.addEventListener("DOMContentLoaded", () => {
       },
       { data: "nummerRechnung", width: 50 },
       { data: "gelieferteMenge", type: "numeric", width: 40 },
+      {
+        data: "preisRechnung",
+        type: "numeric",
+        numericFormat: { pattern: "0.00 €" },
+        width: 50,
+      },
+      {
+        data: "datumDdt",
+        type: "date",
+        dateFormat: "DD.MM.YYYY",
+        dateFormats: ["DD.MM.YYYY"],
+        correctFormat: true,
+        width: 60,
+      },
+      { data: "nummerDdt", width: 50 },
+      { data: "mengeDdt", type: "numeric", width: 40 },
+      { data: "nummerGutschrift", width: 50 },
+      { data: "mengeGutschrift", type: "numeric", width: 40 },
       { data: "anmerkungen", type: "text", width: 50 },
     ],
     copyPaste: true,
@@ -1006,6 +1528,8 @@ document.addEventListener("DOMContentLoaded", () => {
               "nummerAuftrag",
               "datumRechnung",
               "nummerRechnung",
+              "datumDdt",
+              "nummerDdt",
             ];
 
             props.forEach((prop) => {
@@ -1111,15 +1635,34 @@ async function handleExportExcel() {
     );
 
     const defaultExcelPath = await store?.get<string>("defaultExcelPath");
+    let filePath = defaultExcelPath || null;
+
+    if ((await store?.get<boolean>("exportPreview")) !== false) {
+      const preview = await invoke<ExportReport>("export_to_excel", {
+        data: confirmedData,
+        filePath,
+        dryRun: true,
+      });
+
+      if (!preview.diff) {
+        showToast(preview.message, "info");
+        return;
+      }
+      if (!(await showExportPreview(preview))) {
+        showToast("Esportazione annullata.", "info");
+        return;
+      }
+      filePath = preview.filePath;
+    }
 
-    const msg = await invoke<string>("export_to_excel", {
+    const report = await invoke<ExportReport>("export_to_excel", {
       data: confirmedData,
-      filePath: defaultExcelPath || null,
+      filePath,
+      dryRun: false,
     });
 
-    if (msg !== "Demolizione") {
-      showToast(msg, "success");
-    }
+    showToast(report.message, report.diff ? "success" : "info");
+    if (report.diff?.ambiguous.length) flagAmbiguousRows(report.diff.ambiguous);
 
     isProcessing = false;
   } catch (err) {
@@ -1133,6 +1676,118 @@ async function handleExportExcel() {
   }
 }
 
+function formatCandidates(match: AmbiguousMatch): string {
+  return match.candidates
+    .map((c) => `riga ${c.row} "${c.produkt}" (${Math.round(c.score * 100)}%)`)
+    .join(", ");
+}
+
+function flagAmbiguousRows(ambiguous: AmbiguousMatch[]) {
+  if (!hot) return;
+  const data = hot.getSourceData() as PdfDataRow[];
+  ambiguous.forEach((match) => {
+    const index = data.findIndex(
+      (row) =>
+        row.confirmed &&
+        row.nummerAuftrag === match.row.nummerAuftrag &&
+        row.produkt === match.row.produkt
+    );
+    if (index < 0) return;
+    hot!.setDataAtRowProp(index, "confirmed", false);
+    hot!.setDataAtRowProp(index, "warnings", true);
+    hot!.setDataAtRowProp(
+      index,
+      "anmerkungen",
+      `Corrispondenza incerta: ${formatCandidates(match)}`
+    );
+  });
+}
+
+function showExportPreview(report: ExportReport): Promise<boolean> {
+  const modal = document.getElementById("export-preview-modal");
+  const summary = document.getElementById("export-preview-summary");
+  const list = document.getElementById("export-preview-list");
+  const confirmBtn = document.getElementById("confirm-export-btn");
+  const cancelBtn = document.getElementById("cancel-export-btn");
+  if (!modal || !summary || !list || !confirmBtn || !cancelBtn || !report.diff)
+    return Promise.resolve(true);
+
+  const diff = report.diff;
+  summary.textContent = report.message;
+  list.innerHTML = "";
+
+  const addItem = (text: string, className?: string) => {
+    const li = document.createElement("li");
+    li.textContent = text;
+    if (className) li.className = className;
+    list.appendChild(li);
+  };
+
+  diff.warnings.forEach((warning) => addItem(`⚠ ${warning}`));
+  diff.updates.forEach((update) => {
+    const changes = update.changes
+      .map((c) => `${c.column}: "${c.oldValue}" → "${c.newValue}"`)
+      .join(", ");
+    addItem(
+      `Riga ${update.row} (${update.nummerAuftrag} / ${update.produkt}): ${changes}`
+    );
+  });
+  diff.notes.forEach((note) => {
+    addItem(
+      `Nota riga ${note.row} (${note.nummerAuftrag} / ${note.produkt}): "${note.change.oldValue}" → "${note.change.newValue}"`
+    );
+  });
+  diff.insertions.forEach((insertion) => {
+    addItem(
+      `Nuova riga prima della riga ${insertion.beforeRow}: ${
+        insertion.row.nummerAuftrag || "-"
+      } / ${insertion.row.produkt || "-"}`
+    );
+  });
+  diff.reconciliation?.findings.forEach((finding) => {
+    addItem(
+      `⚠ ${finding.nummerAuftrag} / ${finding.produkt}: ${finding.note}`
+    );
+  });
+  diff.fuzzyMatches.forEach((match) => {
+    addItem(
+      `≈ ${match.nummerAuftrag} / ${match.produkt}: associato a riga ${
+        match.candidate.row
+      } "${match.candidate.produkt}" (${Math.round(
+        match.candidate.score * 100
+      )}%)`
+    );
+  });
+  diff.ambiguous.forEach((match) => {
+    addItem(
+      `? ${match.row.nummerAuftrag || "-"} / ${
+        match.row.produkt || "-"
+      }: corrispondenza incerta con ${formatCandidates(match)}`
+    );
+  });
+  diff.unmatchable.forEach((row) => {
+    addItem(
+      `Senza corrispondenza: ${row.nummerAuftrag || "-"} / ${
+        row.produkt || "-"
+      }`
+    );
+  });
+  if (!list.children.length) addItem("Nessuna modifica.", "empty-state");
+
+  modal.style.display = "flex";
+
+  return new Promise((resolve) => {
+    const close = (approved: boolean) => {
+      modal.style.display = "none";
+      confirmBtn.onclick = null;
+      cancelBtn.onclick = null;
+      resolve(approved);
+    };
+    confirmBtn.onclick = () => close(true);
+    cancelBtn.onclick = () => close(false);
+  });
+}
+
 function showToast(text: string, type: "success" | "error" | "info" = "info") {
   let container = document.getElementById("toast-container");
   if (!container) {
@@ -1221,6 +1876,406 @@ async function loadAndRenderCorrections() {
   }
 }
 
+async function loadExportJournal() {
+  const listEl = document.getElementById("export-journal-list");
+  if (!listEl) return;
+
+  try {
+    const journal = await invoke<JournalEntry[]>("get_export_journal");
+
+    listEl.innerHTML = "";
+
+    if (journal.length === 0) {
+      listEl.innerHTML =
+        '<li class="empty-state">Nessuna esportazione registrata.</li>';
+      return;
+    }
+
+    journal.forEach((entry) => {
+      const li = document.createElement("li");
+
+      const fileName = entry.workbook.split(/[\\/]/).pop() || entry.workbook;
+      const date = new Date(entry.timestamp).toLocaleString("it-IT");
+      const description =
+        entry.kind === "restore"
+          ? `ripristino di ${entry.restoredFrom || "-"}`
+          : `${entry.updated} aggiornati, ${entry.inserted} inseriti`;
+
+      const textDiv = document.createElement("div");
+      textDiv.className = "correction-text";
+      textDiv.title = entry.workbook;
+      textDiv.textContent = `${date} · ${fileName} · ${description}${
+        entry.restoredAt ? " (ripristinata)" : ""
+      }`;
+      li.appendChild(textDiv);
+
+      const restoreBtn = document.createElement("button");
+      restoreBtn.className = "btn btn-secondary";
+      restoreBtn.textContent = "Ripristina";
+      restoreBtn.title = "Ripristina il file allo stato precedente";
+      restoreBtn.addEventListener("click", async () => {
+        if (
+          !confirm(
+            `Ripristinare ${fileName} allo stato precedente a questa operazione?`
+          )
+        )
+          return;
+        try {
+          const msg = await invoke<string>("restore_export", { id: entry.id });
+          showToast(msg, "success");
+          await loadExportJournal();
+        } catch (e) {
+          console.error(e);
+          showToast(`${e}`, "error");
+        }
+      });
+      li.appendChild(restoreBtn);
+
+      listEl.appendChild(li);
+    });
+  } catch (e) {
+    console.error("Errore durante il caricamento del giornale:", e);
+    listEl.innerHTML =
+      '<li class="empty-state">Errore durante il caricamento del giornale.</li>';
+  }
+}
+
+async function loadLlmProfiles() {
+  const select = document.getElementById(
+    "setting-llm-profile"
+  ) as HTMLSelectElement | null;
+  if (!select) return;
+
+  try {
+    const settings = await invoke<{ profiles: LlmProfile[]; active: string }>(
+      "get_llm_profiles"
+    );
+    llmProfiles = settings.profiles;
+
+    select.innerHTML = "";
+    llmProfiles.forEach((profile) => {
+      const option = document.createElement("option");
+      option.value = profile.name;
+      option.textContent = profile.name;
+      select.appendChild(option);
+    });
+
+    const newOption = document.createElement("option");
+    newOption.value = "";
+    newOption.textContent = "+ Nuovo profilo";
+    select.appendChild(newOption);
+
+    select.value = settings.active;
+    fillLlmProfileFields(settings.active);
+    select.onchange = () => fillLlmProfileFields(select.value);
+  } catch (e) {
+    console.error("Errore durante il caricamento dei profili LLM:", e);
+  }
+}
+
+function fillLlmProfileFields(name: string) {
+  const profile = llmProfiles.find((p) => p.name === name);
+
+  const nameInput = document.getElementById(
+    "setting-llm-name"
+  ) as HTMLInputElement;
+  const providerSelect = document.getElementById(
+    "setting-llm-provider"
+  ) as HTMLSelectElement;
+  const urlInput = document.getElementById(
+    "setting-llm-url"
+  ) as HTMLInputElement;
+  const modelInput = document.getElementById(
+    "setting-llm-model"
+  ) as HTMLInputElement;
+
+  const keyInput = document.getElementById(
+    "setting-llm-key"
+  ) as HTMLInputElement;
+
+  nameInput.value = profile?.name || "";
+  providerSelect.value = profile?.provider || "mistral";
+  urlInput.value = profile?.baseUrl || "";
+  modelInput.value = profile?.model || "";
+
+  // Only OpenAI-compatible profiles have their own key; Mistral uses the key above.
+  const toggleKey = () => {
+    keyInput.style.display = providerSelect.value === "openAi" ? "" : "none";
+  };
+  providerSelect.onchange = toggleKey;
+  toggleKey();
+  keyInput.value = "";
+  if (profile?.provider === "openAi") {
+    invoke<string>("get_api_key", { profile: profile.name })
+      .then((key) => (keyInput.value = key))
+      .catch(() => {});
+  }
+}
+
+async function saveLlmProfile() {
+  const select = document.getElementById(
+    "setting-llm-profile"
+  ) as HTMLSelectElement | null;
+  if (!select) return;
+
+  const name = (
+    document.getElementById("setting-llm-name") as HTMLInputElement
+  ).value.trim();
+  if (!name) return;
+
+  const edited: LlmProfile = {
+    name,
+    provider: (
+      document.getElementById("setting-llm-provider") as HTMLSelectElement
+    ).value as LlmProfile["provider"],
+    baseUrl:
+      (
+        document.getElementById("setting-llm-url") as HTMLInputElement
+      ).value.trim() || null,
+    model:
+      (
+        document.getElementById("setting-llm-model") as HTMLInputElement
+      ).value.trim() || null,
+  };
+
+  const profiles = llmProfiles.filter(
+    (p) => p.name !== select.value && p.name !== name
+  );
+  profiles.push(edited);
+
+  await invoke("save_llm_profiles", { profiles, active: name });
+  if (edited.provider === "openAi") {
+    await invoke("save_api_key", {
+      key: (document.getElementById("setting-llm-key") as HTMLInputElement)
+        .value,
+      profile: name,
+    });
+  }
+  llmProfiles = profiles;
+}
+
+async function loadExcelMappings() {
+  const select = document.getElementById(
+    "setting-excel-mapping"
+  ) as HTMLSelectElement | null;
+  if (!select) return;
+
+  try {
+    const settings = await invoke<{ mappings: ExcelMapping[]; active: string }>(
+      "get_excel_mappings"
+    );
+    excelMappings = settings.mappings;
+
+    select.innerHTML = "";
+    excelMappings.forEach((mapping) => {
+      const option = document.createElement("option");
+      option.value = mapping.name;
+      option.textContent = mapping.name;
+      select.appendChild(option);
+    });
+
+    const newOption = document.createElement("option");
+    newOption.value = "";
+    newOption.textContent = "+ Nuova mappatura";
+    select.appendChild(newOption);
+
+    select.value = settings.active;
+    fillExcelMappingFields(settings.active);
+    select.onchange = () => fillExcelMappingFields(select.value);
+  } catch (e) {
+    console.error("Errore durante il caricamento delle mappature Excel:", e);
+  }
+}
+
+function fillExcelMappingFields(name: string) {
+  const mapping = excelMappings.find((m) => m.name === name);
+  const template = mapping || excelMappings[0];
+
+  const nameInput = document.getElementById(
+    "setting-excel-mapping-name"
+  ) as HTMLInputElement;
+  const jsonInput = document.getElementById(
+    "setting-excel-mapping-json"
+  ) as HTMLTextAreaElement;
+
+  nameInput.value = mapping?.name || "";
+  jsonInput.value = template
+    ? JSON.stringify(
+        { headerAnchor: template.headerAnchor, columns: template.columns },
+        null,
+        2
+      )
+    : "";
+}
+
+async function saveExcelMapping() {
+  const select = document.getElementById(
+    "setting-excel-mapping"
+  ) as HTMLSelectElement | null;
+  if (!select) return;
+
+  const name = (
+    document.getElementById("setting-excel-mapping-name") as HTMLInputElement
+  ).value.trim();
+  const json = (
+    document.getElementById("setting-excel-mapping-json") as HTMLTextAreaElement
+  ).value.trim();
+  if (!name || !json) return;
+
+  let parsed: Omit<ExcelMapping, "name">;
+  try {
+    parsed = JSON.parse(json);
+  } catch (e) {
+    throw `Mappatura Excel non valida: ${e}`;
+  }
+
+  const mappings = excelMappings.filter(
+    (m) => m.name !== select.value && m.name !== name
+  );
+  mappings.push({ ...parsed, name });
+
+  await invoke("save_excel_mappings", { mappings, active: name });
+  excelMappings = mappings;
+}
+
+async function loadCorrectionRules() {
+  const input = document.getElementById(
+    "setting-correction-rules"
+  ) as HTMLTextAreaElement | null;
+  if (!input) return;
+
+  try {
+    const rules = await invoke<CorrectionRule[]>("get_correction_rules");
+    input.value = rules.length ? JSON.stringify(rules, null, 2) : "";
+  } catch (e) {
+    console.error("Errore durante il caricamento delle regole di correzione:", e);
+  }
+  renderCorrectionRuleErrors(await loadCorrectionRuleErrors());
+}
+
+async function loadCorrectionRuleErrors(): Promise<string[]> {
+  try {
+    return await invoke<string[]>("get_correction_rule_errors");
+  } catch (e) {
+    console.error("Errore durante il controllo delle regole di correzione:", e);
+    return [];
+  }
+}
+
+function renderCorrectionRuleErrors(errors: string[]) {
+  const list = document.getElementById("setting-correction-rule-errors");
+  if (!list) return;
+  list.innerHTML = "";
+  errors.forEach((error) => {
+    const li = document.createElement("li");
+    li.textContent = `⚠ Ignorata: ${error}`;
+    list.appendChild(li);
+  });
+  list.style.display = errors.length ? "" : "none";
+}
+
+function readCorrectionRules(): CorrectionRule[] {
+  const json = (
+    document.getElementById("setting-correction-rules") as HTMLTextAreaElement
+  ).value.trim();
+  if (!json) return [];
+  try {
+    return JSON.parse(json);
+  } catch (e) {
+    throw `Regole di correzione non valide: ${e}`;
+  }
+}
+
+async function loadProductCatalog() {
+  const input = document.getElementById(
+    "setting-product-catalog"
+  ) as HTMLTextAreaElement | null;
+  if (!input) return;
+
+  try {
+    const entries = await invoke<CatalogEntry[]>("get_product_catalog");
+    input.value = entries.length ? JSON.stringify(entries, null, 2) : "";
+  } catch (e) {
+    console.error("Errore durante il caricamento del catalogo prodotti:", e);
+  }
+}
+
+function readProductCatalog(): CatalogEntry[] {
+  const json = (
+    document.getElementById("setting-product-catalog") as HTMLTextAreaElement
+  ).value.trim();
+  if (!json) return [];
+  try {
+    return JSON.parse(json);
+  } catch (e) {
+    throw `Catalogo prodotti non valido: ${e}`;
+  }
+}
+
+async function loadFilenamePatterns() {
+  const input = document.getElementById(
+    "setting-filename-patterns"
+  ) as HTMLTextAreaElement | null;
+  const testBtn = document.getElementById(
+    "test-filename-patterns-btn"
+  ) as HTMLButtonElement | null;
+  if (!input) return;
+
+  try {
+    const patterns = await invoke<FilenamePattern[]>("get_filename_patterns");
+    input.value = JSON.stringify(patterns, null, 2);
+  } catch (e) {
+    console.error("Errore durante il caricamento degli schemi nomi file:", e);
+  }
+
+  if (testBtn) testBtn.onclick = testFilenamePatterns;
+}
+
+function readFilenamePatterns(): FilenamePattern[] {
+  const json = (
+    document.getElementById("setting-filename-patterns") as HTMLTextAreaElement
+  ).value.trim();
+  try {
+    return JSON.parse(json);
+  } catch (e) {
+    throw `Schemi nomi file non validi: ${e}`;
+  }
+}
+
+async function testFilenamePatterns() {
+  const output = document.getElementById("filename-pattern-result");
+  const samples = (
+    document.getElementById("setting-filename-sample") as HTMLInputElement
+  ).value.split(",");
+  if (!output) return;
+
+  try {
+    const results = await invoke<
+      { sample: string; pattern: string | null; metadata: FileMetadata }[]
+    >("test_filename_patterns", {
+      patterns: readFilenamePatterns(),
+      samples,
+    });
+    output.textContent = results
+      .map(({ sample, pattern, metadata }) =>
+        [
+          `${sample} → ${pattern ?? "nessuno schema"} (${metadata.docType})`,
+          `  ordine: ${metadata.nummerAuftrag ?? "-"}, data ordine: ${
+            metadata.datumAuftrag ?? "-"
+          }, data fattura: ${metadata.datumRechnung ?? "-"}, data DDT: ${
+            metadata.datumDdt ?? "-"
+          }`,
+          `  fornitore: ${metadata.lieferant ?? "-"}, cliente: ${
+            metadata.kunde ?? "-"
+          }${metadata.warnings ? " ⚠" : ""}`,
+        ].join("\n")
+      )
+      .join("\n");
+  } catch (e) {
+    output.textContent = String(e);
+  }
+}
+
 async function reAnalyzeRow(row: number) {
   if (!hot) return;
 
@@ -1235,21 +2290,51 @@ async function reAnalyzeRow(row: number) {
   showToast("Analizza nuovamente il PDF...", "info");
 
   try {
-    const result = await invoke<AiResponse>("analyze_document", {
-      path: rowData.fullPath,
-      docType: rowData.docType,
-    });
+    const result = await analyzeDocument(
+      rowData.fullPath,
+      rowData.docType,
+      true
+    );
 
     const products = result.produkte;
+    const docType = result.docType ?? rowData.docType;
 
     if (products && Array.isArray(products) && products.length > 0) {
       hot.batch(() => {
         const firstProd = products[0];
 
-        if (rowData.docType === "auftrag") {
+        hot!.setDataAtRowProp(row, "docType", docType);
+        Object.entries(headerFields(rowData, result)).forEach(
+          ([prop, value]) => hot!.setDataAtRowProp(row, prop, value)
+        );
+        if (docType === "auftrag") {
           hot!.setDataAtRowProp(row, "menge", firstProd.menge);
           hot!.setDataAtRowProp(row, "waehrung", firstProd.waehrung);
           hot!.setDataAtRowProp(row, "preis", firstProd.preis);
+        } else if (docType === "gutschrift") {
+          hot!.setDataAtRowProp(
+            row,
+            "mengeGutschrift",
+            creditedQuantity(firstProd)
+          );
+          hot!.setDataAtRowProp(
+            row,
+            "nummerGutschrift",
+            result.nummerGutschrift
+          );
+          hot!.setDataAtRowProp(row, "datumRechnung", null);
+          if (firstProd.nummerAuftrag) {
+            hot!.setDataAtRowProp(row, "nummerAuftrag", firstProd.nummerAuftrag);
+          }
+        } else if (docType === "lieferschein") {
+          hot!.setDataAtRowProp(row, "mengeDdt", firstProd.gelieferteMenge);
+          hot!.setDataAtRowProp(row, "nummerDdt", result.nummerDdt);
+          if (firstProd.nummerAuftrag) {
+            hot!.setDataAtRowProp(row, "nummerAuftrag", firstProd.nummerAuftrag);
+          }
+          if (result.datumDdt) {
+            hot!.setDataAtRowProp(row, "datumDdt", result.datumDdt);
+          }
         } else {
           hot!.setDataAtRowProp(
             row,
@@ -1257,10 +2342,40 @@ async function reAnalyzeRow(row: number) {
             firstProd.gelieferteMenge
           );
           hot!.setDataAtRowProp(row, "nummerRechnung", result.nummerRechnung);
+          hot!.setDataAtRowProp(row, "preisRechnung", firstProd.preis);
+          if (firstProd.nummerAuftrag) {
+            hot!.setDataAtRowProp(row, "nummerAuftrag", firstProd.nummerAuftrag);
+          }
+          if (result.datumRechnung) {
+            hot!.setDataAtRowProp(row, "datumRechnung", result.datumRechnung);
+          }
         }
         hot!.setDataAtRowProp(row, "produkt", firstProd.produkt);
+        hot!.setDataAtRowProp(
+          row,
+          "correctionNote",
+          correctionNote(result, firstProd.produkt)
+        );
+        hot!.setDataAtRowProp(
+          row,
+          "artikelnummer",
+          firstProd.artikelnummer ?? null
+        );
+        hot!.setDataAtRowProp(
+          row,
+          "einheit",
+          productUnit(result, firstProd)
+        );
+        hot!.setDataAtRowProp(row, "documentHash", result.document?.hash);
 
-        hot!.setDataAtRowProp(row, "anmerkungen", "");
+        hot!.setDataAtRowProp(
+          row,
+          "anmerkungen",
+          result.mismatches?.join("; ") || ""
+        );
+        if (result.mismatches?.length) {
+          hot!.setDataAtRowProp(row, "warnings", true);
+        }
         if (products.length > 1) {
           const extraProducts = products.slice(1);
           hot!.alter("insert_row_below", row, extraProducts.length);
@@ -1269,11 +2384,65 @@ async function reAnalyzeRow(row: number) {
             const newRowIdx = row + 1 + i;
 
             hot!.setDataAtRowProp(newRowIdx, "produkt", prod.produkt);
-
-            if (rowData.docType === "auftrag") {
+            hot!.setDataAtRowProp(
+              newRowIdx,
+              "correctionNote",
+              correctionNote(result, prod.produkt)
+            );
+            hot!.setDataAtRowProp(
+              newRowIdx,
+              "artikelnummer",
+              prod.artikelnummer ?? null
+            );
+            hot!.setDataAtRowProp(
+              newRowIdx,
+              "einheit",
+              productUnit(result, prod)
+            );
+            hot!.setDataAtRowProp(
+              newRowIdx,
+              "documentHash",
+              result.document?.hash
+            );
+
+            if (docType === "auftrag") {
               hot!.setDataAtRowProp(newRowIdx, "menge", prod.menge);
               hot!.setDataAtRowProp(newRowIdx, "waehrung", prod.waehrung);
               hot!.setDataAtRowProp(newRowIdx, "preis", prod.preis);
+            } else if (docType === "gutschrift") {
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "mengeGutschrift",
+                creditedQuantity(prod)
+              );
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "nummerGutschrift",
+                result.nummerGutschrift
+              );
+              hot!.setDataAtRowProp(newRowIdx, "datumRechnung", null);
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "nummerAuftrag",
+                prod.nummerAuftrag || rowData.nummerAuftrag
+              );
+            } else if (docType === "lieferschein") {
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "mengeDdt",
+                prod.gelieferteMenge
+              );
+              hot!.setDataAtRowProp(newRowIdx, "nummerDdt", result.nummerDdt);
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "nummerAuftrag",
+                prod.nummerAuftrag || rowData.nummerAuftrag
+              );
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "datumDdt",
+                result.datumDdt || rowData.datumDdt
+              );
             } else {
               hot!.setDataAtRowProp(
                 newRowIdx,
@@ -1285,6 +2454,17 @@ async function reAnalyzeRow(row: number) {
                 "nummerRechnung",
                 result.nummerRechnung
               );
+              hot!.setDataAtRowProp(newRowIdx, "preisRechnung", prod.preis);
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "nummerAuftrag",
+                prod.nummerAuftrag || rowData.nummerAuftrag
+              );
+              hot!.setDataAtRowProp(
+                newRowIdx,
+                "datumRechnung",
+                result.datumRechnung || rowData.datumRechnung
+              );
             }
           });
         }